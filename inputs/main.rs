@@ -0,0 +1,14 @@
+type List = [() | ((), List)];
+
+fn generate(condition: ()) -> List {
+    if condition {
+        List(tuple())
+    } else {
+        List(tuple(tuple(), generate(condition)))
+    }
+}
+
+fn main() -> ((), List) {
+    let condition = invent();
+    tuple(condition, generate(condition))
+}
@@ -1,4 +1,4 @@
-fn function(test, a, b, c, d) {
+fn function(test: (), a: (), b: (), c: (), d: ()) -> () {
     if test {
         if test {
             a
@@ -12,4 +12,4 @@ fn function(test, a, b, c, d) {
             d
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,11 @@
+type List = [() | ((), List)];
+
+fn single() -> List {
+    List(tuple())
+}
+
+fn main() -> () {
+    let list = single();
+    let pair = tuple(list, list);
+    print(list, pair)
+}
@@ -0,0 +1,26 @@
+type List = [() | ((), List)];
+
+fn generate(condition: ()) -> List {
+    if condition {
+        List(tuple())
+    } else {
+        List(tuple(tuple(), generate(condition)))
+    }
+}
+
+fn spawn(list: List) -> () {
+    invent()
+}
+
+fn send(list: List) -> () {
+    spawn(list)
+}
+
+fn main() -> ((), List) {
+    let a = invent();
+    let b = invent();
+    let sent = generate(a);
+    let kept = generate(b);
+    let done = send(sent);
+    tuple(done, kept)
+}
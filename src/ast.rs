@@ -1,4 +1,8 @@
-use std::{collections::HashMap, fmt::Display};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    hash::{Hash, Hasher},
+};
 
 use crate::types::Type;
 
@@ -29,12 +33,21 @@ pub struct Arg {
 pub struct Block {
     pub stmnts: Vec<Statement>,
     pub ret: Expr,
+    pub ret_span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct Statement {
     pub ident: Ident,
     pub value: Expr,
+    pub span: Span,
+}
+
+/// Where something is in the source, as byte offsets.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
 }
 
 #[derive(Debug, Clone)]
@@ -70,6 +83,12 @@ impl Display for Ident {
     }
 }
 
+// Moving code around doesn't change what it means, so spans don't take part in the hashes
+// that analysis summaries are cached by
+impl Hash for Span {
+    fn hash<H: Hasher>(&self, _state: &mut H) {}
+}
+
 impl Module {
     pub fn enum_constructors(&self) -> HashMap<String, Type> {
         self.ty_defs
@@ -0,0 +1,35 @@
+#pragma once
+#include <stdlib.h>
+
+// Where the runtime gets its memory from. Frees are told the size that was allocated.
+struct allocator {
+    void *(*alloc)(void *ctx, size_t size);
+    void (*free)(void *ctx, void *ptr, size_t size);
+    void *ctx;
+};
+
+#ifdef ALLOC_HOOKS
+static void *alloc_malloc(void *ctx, size_t size) {
+    (void) ctx;
+    return malloc(size);
+}
+
+static void alloc_free(void *ctx, void *ptr, size_t size) {
+    (void) ctx;
+    (void) size;
+    free(ptr);
+}
+
+static struct allocator rt_allocator = {alloc_malloc, alloc_free, NULL};
+
+// Lets the host provide its own allocator, before the program allocates anything
+void set_allocator(struct allocator allocator) {
+    rt_allocator = allocator;
+}
+
+#define RT_ALLOC(size) rt_allocator.alloc(rt_allocator.ctx, size)
+#define RT_FREE(ptr, size) rt_allocator.free(rt_allocator.ctx, ptr, size)
+#else
+#define RT_ALLOC(size) malloc(size)
+#define RT_FREE(ptr, size) free(ptr)
+#endif
@@ -0,0 +1,1316 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    slots,
+    structure::{self, Shape},
+    walk_blocks, Backend, Function, Module, Release,
+};
+use crate::{
+    ast::Span,
+    cfg::{mem_manage::Strategy, DebugNames, Statement, Terminator, Value},
+    types::Type,
+};
+
+const MAKEFILE: &str = include_str!("Makefile");
+const STD_BASE: &str = include_str!("std_base.c");
+const REGION: &str = include_str!("region.h");
+const RC: &str = include_str!("rc.h");
+const ALLOC: &str = include_str!("alloc.h");
+
+/// Writes the module as C to `program.c`, with its prototypes in `program.h`, its types
+/// and their runtime functions in `types.h`, and a Makefile to build it.
+pub struct CBackend {
+    dir: PathBuf,
+    program: Lines<fs::File>,
+    header: fs::File,
+    types: fs::File,
+    /// The variable each local of the current function is stored in
+    slots: HashMap<usize, usize>,
+    /// The source names of the current function's places
+    names: DebugNames,
+    /// Places of the current function that outlive it
+    escaping: HashSet<usize>,
+    /// The source line the current function's code was last attributed to
+    line: Option<usize>,
+}
+
+// Counts the lines written so far, so that `#line` can point back at the C itself
+struct Lines<W> {
+    inner: W,
+    count: usize,
+}
+
+impl<W: io::Write> io::Write for Lines<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += buf[..written].iter().filter(|&&b| b == b'\n').count();
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl CBackend {
+    pub fn create(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            program: Lines {
+                inner: fs::File::create(dir.join("program.c"))?,
+                count: 0,
+            },
+            header: fs::File::create(dir.join("program.h"))?,
+            types: fs::File::create(dir.join("types.h"))?,
+            slots: HashMap::new(),
+            names: DebugNames::default(),
+            escaping: HashSet::new(),
+            line: None,
+        })
+    }
+
+    // Attributes the code that follows to the line of the source it was compiled from,
+    // quoting that line the first time it comes up
+    fn locate(&mut self, module: &Module, span: Option<Span>) -> io::Result<()> {
+        let (Some(source), Some(span)) = (&module.opts.source, span) else {
+            return Ok(());
+        };
+
+        let (line, text) = source.line(span.start);
+        if self.line != Some(line) {
+            writeln!(self.program, "// {}", text.trim())?;
+            self.line = Some(line);
+        }
+        writeln!(
+            self.program,
+            "#line {line} {:?}",
+            source.path.to_string_lossy()
+        )
+    }
+
+    fn r(&self, p: &usize) -> String {
+        local(&self.names, self.slots.get(p).copied().unwrap_or(*p))
+    }
+
+    fn shapes(&mut self, module: &Module, func: &Function, shapes: &[Shape]) -> io::Result<()> {
+        let blocks = &func.cfg.basic_blocks;
+
+        for shape in shapes {
+            match shape {
+                Shape::Block(b) => {
+                    for (i, stmnt) in blocks[*b].stmnts.iter().enumerate() {
+                        self.statement(module, func, (*b, i), stmnt)?;
+                    }
+                }
+                Shape::Edge(from, to) => self.phi_copies(func, *from, *to)?,
+                Shape::If { block, then, elsee } => {
+                    let Some(Terminator::IfElse { cond, .. }) = &blocks[*block].terminator else {
+                        unreachable!()
+                    };
+
+                    self.locate(module, blocks[*block].term_span)?;
+                    writeln!(self.program, "if ({}) {{", self.condition(module, func, cond))?;
+                    self.shapes(module, func, then)?;
+                    writeln!(self.program, "}} else {{")?;
+                    self.shapes(module, func, elsee)?;
+                    writeln!(self.program, "}}")?;
+                }
+                Shape::Return(b) => {
+                    let Some(Terminator::Return(p)) = &blocks[*b].terminator else {
+                        unreachable!()
+                    };
+                    self.locate(module, blocks[*b].term_span)?;
+                    self.ret(module, func, p)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Assigns the phis of `to` that take their value from `from`
+    fn phi_copies(&mut self, func: &Function, from: usize, to: usize) -> io::Result<()> {
+        for phi in &func.cfg.basic_blocks[to].phi {
+            if let Some(desired_place) = phi.opts.get(&from) {
+                let to_value = !func.deps.heap_stored(phi.place);
+                writeln!(
+                    self.program,
+                    "{} = {}{};",
+                    self.r(&phi.place),
+                    deref(func, desired_place, to_value),
+                    self.r(desired_place)
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn ret(&mut self, module: &Module, func: &Function, p: &usize) -> io::Result<()> {
+        if module.opts.strategy == Strategy::Region {
+            writeln!(self.program, "region_free(&local);")?;
+        }
+        let to_value = !func.returns_ptr();
+        writeln!(
+            self.program,
+            "return {}{};",
+            deref(func, p, to_value),
+            self.r(p)
+        )
+    }
+
+    // Stack locals are passed by address, everything else already is a pointer
+    fn ptr_to(&self, func: &Function, p: &usize) -> String {
+        let by_ref = !func.deps.nodes[*p].allocated() && !func.is_ref(*p);
+        let amp = if by_ref { "&" } else { "" };
+        format!("{amp}{}", self.r(p))
+    }
+
+    // Enums are true unless they're their first variant, like in the LLVM backend. Anything
+    // else has no discriminant and is always true.
+    fn condition(&self, module: &Module, func: &Function, cond: &usize) -> String {
+        if !matches!(module.resolve(&func.cfg.place_tys[*cond]), Type::Enum(_)) {
+            return "1".to_string();
+        }
+
+        let through = func.deps.heap_stored(*cond) || func.is_ref(*cond);
+        let field = if through { "->" } else { "." };
+        format!("{}{field}disc", self.r(cond))
+    }
+
+    fn region(&self, p: &usize) -> &'static str {
+        match self.escaping.contains(p) {
+            true => "rgn",
+            false => "&local",
+        }
+    }
+}
+
+// Locals are named after their place and what it was called in the source, like `r3_list`.
+// Starting with `r` and the place keeps them from clashing with anything else in the C.
+fn local(names: &DebugNames, place: usize) -> String {
+    match names.get(place) {
+        Some(name) => format!("r{place}_{}", name.0.replace('\'', "_")),
+        None => format!("r{place}"),
+    }
+}
+
+// Args and caller buffers are pointers to stack values, so they are read through when
+// copied somewhere that holds a value
+fn deref(func: &Function, from: &usize, to_value: bool) -> &'static str {
+    if func.is_ref(*from) && to_value {
+        "*"
+    } else {
+        ""
+    }
+}
+
+// Values other threads may hold use the atomic versions of the reference counting functions
+fn atomic(module: &Module, func: &Function, place: usize) -> &'static str {
+    match module.atomic(func, place) {
+        true => "_atomic",
+        false => "",
+    }
+}
+
+impl Backend for CBackend {
+    fn prologue(&mut self, module: &Module) -> io::Result<()> {
+        writeln!(self.types, "#pragma once")?;
+        writeln!(self.types, "#include <stdio.h>")?;
+        writeln!(self.types, "#include <stdlib.h>\n")?;
+        let (runtime, runtime_src) = match module.opts.strategy {
+            Strategy::RefCount => ("rc.h", RC),
+            Strategy::Region => ("region.h", REGION),
+        };
+        fs::write(self.dir.join(runtime), runtime_src)?;
+        fs::write(self.dir.join("alloc.h"), ALLOC)?;
+        if module.opts.debug_rc() {
+            writeln!(self.types, "#define RC_DEBUG")?;
+        }
+        if module.opts.alloc_hooks {
+            writeln!(self.types, "#define ALLOC_HOOKS")?;
+        }
+        if module.opts.atomic() {
+            writeln!(self.types, "#define RC_ATOMIC")?;
+        }
+        writeln!(self.types, "#include \"{runtime}\"\n")?;
+
+        writeln!(self.program, "#include \"std.c\"")?;
+        writeln!(self.program, "#include \"program.h\"\n")?;
+        writeln!(self.program, "#include \"types.h\"\n")?;
+        writeln!(self.header, "#include \"types.h\"\n")?;
+
+        Ok(())
+    }
+
+    fn declare_types(&mut self, module: &Module, tys: &[&Type]) -> io::Result<()> {
+        fs::write(self.dir.join("std.c"), STD_BASE)?;
+
+        let mut named_tys: Vec<_> = module.type_map.iter().collect();
+        named_tys.sort_by(|a, b| a.0.cmp(b.0));
+        compile_tys(&mut self.types, tys.iter().copied(), named_tys, module)?;
+        writeln!(
+            self.types,
+            "typedef struct {} unit;",
+            module.type_name(&Type::unit())
+        )?;
+
+        Ok(())
+    }
+
+    fn signature(&mut self, module: &Module, func: &Function) -> io::Result<()> {
+        let (cfg, deps) = (func.cfg, func.deps);
+        let (c, h) = (&mut self.program, &mut self.header);
+
+        let ret_ty = module.type_name(&cfg.place_tys[0]);
+        let alloced = if func.returns_ptr() { "*" } else { "" };
+
+        let mut params: Vec<_> = (1..=cfg.arg_count)
+            .chain(func.arged_new.iter().copied())
+            .map(|p| {
+                let ty = module.type_name(&cfg.place_tys[p]);
+                format!("struct {ty} *{}", local(&cfg.debug_names, p))
+            })
+            .collect();
+
+        // The caller's region holds everything that outlives this call
+        if module.opts.strategy == Strategy::Region {
+            params.push("struct region *rgn".to_string());
+        }
+
+        let signature = format!(
+            "struct {ret_ty} {alloced}P_{}({})",
+            cfg.name,
+            params.join(", ")
+        );
+        writeln!(c, "{signature} {{")?;
+        writeln!(h, "{signature};")?;
+
+        let mut decls = HashMap::new();
+        for p in (cfg.arg_count + 1)..cfg.place_tys.len() {
+            if func.arged_new.contains(&p) {
+                continue;
+            }
+
+            let ptr = if deps.heap_stored(p) { "*" } else { "" };
+
+            decls.insert(
+                p,
+                format!("struct {} {ptr}", module.type_name(&cfg.place_tys[p])),
+            );
+        }
+
+        // Locals that are never alive at the same time share a variable
+        self.slots = slots::color_slots(cfg, deps, &decls);
+        self.names = cfg.debug_names.clone();
+
+        let mut declared: Vec<_> = self
+            .slots
+            .values()
+            .copied()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        declared.sort();
+        for p in declared {
+            writeln!(c, "{}{};", decls[&p], local(&cfg.debug_names, p))?;
+        }
+
+        self.escaping = func.aliases.escaping();
+        if module.opts.strategy == Strategy::Region {
+            writeln!(c, "struct region local = {{0}};")?;
+        }
+
+        Ok(())
+    }
+
+    // Functions are written as nested ifs when their blocks allow it, and with a label for
+    // every block otherwise
+    fn body(&mut self, module: &Module, func: &Function) -> io::Result<()> {
+        match structure::structure(func.cfg) {
+            Some(shapes) => self.shapes(module, func, &shapes),
+            None => walk_blocks(self, module, func),
+        }
+    }
+
+    fn block(&mut self, _module: &Module, _func: &Function, block: usize) -> io::Result<()> {
+        writeln!(self.program, "L_{block}:")
+    }
+
+    fn statement(
+        &mut self,
+        module: &Module,
+        func: &Function,
+        _point: (usize, usize),
+        stmnt: &Statement,
+    ) -> io::Result<()> {
+        let (cfg, deps, opts) = (func.cfg, func.deps, &module.opts);
+
+        match stmnt {
+            Statement::Assign(a) => {
+                self.locate(module, a.span)?;
+
+                let new_buffers = match &a.value {
+                    Value::Call { func, .. } => module.call_buffers(func),
+                    Value::Place(_) => vec![],
+                };
+
+                for (i, new_ty) in new_buffers.iter().enumerate() {
+                    writeln!(
+                        self.program,
+                        "struct {} r{}n{i};",
+                        module.type_name(new_ty),
+                        a.place
+                    )?;
+                }
+
+                let c_name = module.type_name(&cfg.place_tys[a.place]);
+
+                let mut line = String::new();
+                if func.arged_new.contains(&a.place) {
+                    line.push('*');
+                }
+                line += &format!("{} = ", self.r(&a.place));
+
+                let mut closing_parens = 0;
+                if a.allocate {
+                    line += &format!("allocate_{c_name}(");
+                    if opts.strategy == Strategy::Region {
+                        line += &format!("{}, ", self.region(&a.place));
+                    }
+                    if opts.debug_rc() {
+                        line += &format!("\"{}:_{}\", ", cfg.name, a.place);
+                    }
+                    closing_parens += 1;
+                }
+
+                match &a.value {
+                    Value::Place(p) => {
+                        let to_value = a.allocate || !deps.heap_stored(a.place);
+                        line += &format!("{}{}", deref(func, p, to_value), self.r(p));
+                    }
+                    Value::Call { func: callee, .. } if callee.0.as_str() == "invent" => {
+                        line += "invent()";
+                    }
+                    Value::Call { func: callee, args } if callee.0.as_str() == "print" => {
+                        // Prints its arguments on one line separated by spaces
+                        for (i, arg) in args.iter().enumerate() {
+                            if i != 0 {
+                                writeln!(self.program, "printf(\" \");")?;
+                            }
+                            let ty = module.type_name(&cfg.place_tys[*arg]);
+                            writeln!(self.program, "print_{ty}({});", self.ptr_to(func, arg))?;
+                        }
+                        writeln!(self.program, "printf(\"\\n\");")?;
+                        line += "invent()";
+                    }
+                    Value::Call { func: callee, args } if callee.0.as_str() == "tuple" => {
+                        // Tuples are built in place, whatever their size
+                        let elems: Vec<_> = args
+                            .iter()
+                            .enumerate()
+                            .map(|(i, arg)| format!(" .e{i} = {}", self.ptr_to(func, arg)))
+                            .collect();
+                        line += &format!("(struct {c_name}) {{{} }}", elems.join(","));
+                    }
+                    Value::Call { func: callee, args } => {
+                        line += &format!("P_{callee}(");
+
+                        let mut call_args: Vec<_> =
+                            args.iter().map(|arg| self.ptr_to(func, arg)).collect();
+                        if !module.fns.contains_key(callee) && module.type_map.contains_key(&callee.0) {
+                            let payload = &cfg.place_tys[args[0]];
+                            let disc = module.variant_of(&cfg.place_tys[a.place], payload);
+                            call_args.insert(0, disc.to_string());
+                        }
+                        call_args
+                            .extend((0..new_buffers.len()).map(|i| format!("&r{}n{i}", a.place)));
+
+                        if opts.strategy == Strategy::Region && module.fns.contains_key(callee) {
+                            call_args.push(self.region(&a.place).to_string());
+                        }
+
+                        line += &call_args.join(", ");
+                        line.push(')');
+                    }
+                }
+
+                line += &")".repeat(closing_parens);
+                writeln!(self.program, "{line};")
+            }
+            Statement::Deallocate(p) => {
+                let ty = module.type_name(&cfg.place_tys[*p]);
+                let atomic = atomic(module, func, *p);
+                writeln!(self.program, "deallocate{atomic}_{ty}({});", self.r(p))
+            }
+            Statement::Dup(rc) => {
+                let ty = module.type_name(&cfg.place_tys[rc.place]);
+                writeln!(
+                    self.program,
+                    "dup{}_{ty}({}, {});",
+                    atomic(module, func, rc.place),
+                    self.r(&rc.place),
+                    rc.count
+                )
+            }
+            Statement::Drop(rc) if !deps.heap_stored(rc.place) => {
+                // A stack value gives up the references it holds instead
+                let value = format!("{}{}", deref(func, &rc.place, true), self.r(&rc.place));
+                let releases = module.releases(func, rc.place);
+                for line in release_lines(module, &value, &cfg.place_tys[rc.place], &releases) {
+                    writeln!(self.program, "{line}")?;
+                }
+                Ok(())
+            }
+            Statement::Drop(rc) => {
+                let ty = module.type_name(&cfg.place_tys[rc.place]);
+                writeln!(
+                    self.program,
+                    "drop{}_{ty}({}, {});",
+                    atomic(module, func, rc.place),
+                    self.r(&rc.place),
+                    rc.count
+                )
+            }
+            Statement::Nop => Ok(()),
+        }
+    }
+
+    fn terminator(
+        &mut self,
+        module: &Module,
+        func: &Function,
+        block: usize,
+        term: &Terminator,
+    ) -> io::Result<()> {
+        self.locate(module, func.cfg.basic_blocks[block].term_span)?;
+
+        // Phis are assigned by their predecessors right before jumping
+        for s in func.cfg.successors(block) {
+            self.phi_copies(func, block, s)?;
+        }
+
+        match term {
+            Terminator::Goto(next) => writeln!(self.program, "goto L_{next};"),
+            Terminator::Return(p) => self.ret(module, func, p),
+            Terminator::IfElse { cond, iff, elsee } => {
+                let cond = self.condition(module, func, cond);
+                writeln!(self.program, "if ({cond}) goto L_{iff};")?;
+                writeln!(self.program, "goto L_{elsee};")
+            }
+        }
+    }
+
+    fn end_function(&mut self, _module: &Module, _func: &Function) -> io::Result<()> {
+        writeln!(self.program, "}}")?;
+
+        // Whatever comes next is attributed to the C again
+        if self.line.take().is_some() {
+            let next = self.program.count + 2;
+            writeln!(self.program, "#line {next} \"program.c\"")?;
+        }
+        writeln!(self.program)
+    }
+
+    fn epilogue(&mut self, module: &Module) -> io::Result<()> {
+        let target = match &module.opts.entry {
+            Some(entry) => {
+                compile_entry(&mut self.program, module, &module.get_fn(entry).unwrap())?;
+                "program"
+            }
+            None => "program.o",
+        };
+
+        let mut makefile = fs::File::create(self.dir.join("Makefile"))?;
+        writeln!(makefile, "TARGET = {target}\n")?;
+        write!(makefile, "{MAKEFILE}")
+    }
+}
+
+// Calls the entry function with the buffers and region it needs, prints what it returns
+// unless that's unit, and then lets go of it
+fn compile_entry(c: &mut impl io::Write, module: &Module, func: &Function) -> io::Result<()> {
+    let cfg = func.cfg;
+    let ret_ty = module.type_name(&cfg.place_tys[0]);
+
+    writeln!(c, "int main(void) {{")?;
+
+    let mut args = vec![];
+    for (i, new) in func.arged_new.iter().enumerate() {
+        let ty = module.type_name(&cfg.place_tys[*new]);
+        writeln!(c, "struct {ty} b{i};")?;
+        args.push(format!("&b{i}"));
+    }
+    if module.opts.strategy == Strategy::Region {
+        writeln!(c, "struct region rgn = {{0}};")?;
+        args.push("&rgn".to_string());
+    }
+
+    let (ptr, amp) = match func.returns_ptr() {
+        true => ("*", ""),
+        false => ("", "&"),
+    };
+    writeln!(
+        c,
+        "struct {ret_ty} {ptr}result = P_{}({});",
+        cfg.name,
+        args.join(", ")
+    )?;
+    // Programs that print for themselves usually return unit
+    if module.resolve(&cfg.place_tys[0]) != &Type::unit() {
+        writeln!(c, "print_{ret_ty}({amp}result);")?;
+        writeln!(c, "printf(\"\\n\");")?;
+    }
+
+    match module.opts.strategy {
+        Strategy::RefCount if func.returns_ptr() => {
+            writeln!(c, "drop{}_{ret_ty}(result, 1);", atomic(module, func, 0))?
+        }
+        Strategy::RefCount => {
+            // A result returned by value still holds references to whatever it points to
+            // on the heap
+            let releases = module.releases(func, 0);
+            for line in release_lines(module, "result", &cfg.place_tys[0], &releases) {
+                writeln!(c, "{line}")?;
+            }
+        }
+        Strategy::Region => writeln!(c, "region_free(&rgn);")?,
+    }
+
+    writeln!(c, "return 0;")?;
+    writeln!(c, "}}")
+}
+
+pub fn compile_tys<'a>(
+    mut h: impl io::Write,
+    anon_tys: impl IntoIterator<Item = &'a Type>,
+    named_tys: impl IntoIterator<Item = (&'a String, &'a Type)>,
+    module: &Module,
+) -> io::Result<HashMap<Type, String>> {
+    let mut remap = HashMap::new();
+
+    // Create the types
+    for anon_ty in anon_tys {
+        compile_ty(&mut h, anon_ty, &mut remap, module)?;
+    }
+    for (name, ty) in named_tys {
+        compile_ty(&mut h, ty, &mut remap, module)?;
+
+        // Create the constructor, which is told which variant it builds
+        if let Type::Enum(_) = ty {
+            let c_name = module.type_name(ty);
+            writeln!(h, "struct {c_name} P_{name}(int disc, void *inner) {{",)?;
+            writeln!(
+                h,
+                "return (struct {c_name}) {{ .disc = disc, .inner = inner }};"
+            )?;
+            writeln!(h, "}}")?;
+        }
+    }
+
+    Ok(remap)
+}
+
+pub fn compile_ty(
+    h: &mut impl io::Write,
+    ty: &Type,
+    remap: &mut HashMap<Type, String>,
+    module: &Module,
+) -> io::Result<()> {
+    // Types with the same structure share their name, so they're only written once
+    let ty = module.resolve(ty);
+    let name = module.type_name(ty);
+    if remap.values().any(|n| *n == name) {
+        return Ok(());
+    }
+    remap.insert(ty.clone(), name.clone());
+    declare_type_fns(h, &name, module)?;
+
+    let printer = match ty {
+        Type::Tuple(t) => {
+            for elem in &t.elems {
+                compile_ty(h, elem, remap, module)?;
+            }
+
+            // Create the tuple type
+            writeln!(h, "// {ty:?}")?;
+            writeln!(h, "struct {name} {{")?;
+            for (i, elem) in t.elems.iter().enumerate() {
+                writeln!(h, "struct {} *e{i};", module.type_name(elem))?;
+            }
+            writeln!(h, "}};\n")?;
+
+            // Printed like a Rust tuple
+            let mut printer = vec!["printf(\"(\");".to_string()];
+            for (i, elem) in t.elems.iter().enumerate() {
+                if i != 0 {
+                    printer.push("printf(\", \");".to_string());
+                }
+                printer.push(format!("print_{}(ptr->e{i});", module.type_name(elem)));
+            }
+            if t.elems.len() == 1 {
+                printer.push("printf(\",\");".to_string());
+            }
+            printer.push("printf(\")\");".to_string());
+
+            printer
+        }
+        Type::Enum(e) => {
+            for variant in &e.variants {
+                compile_ty(h, variant, remap, module)?;
+            }
+
+            // Create the enum struct
+            writeln!(h, "// {ty:?}")?;
+            writeln!(h, "struct {name} {{")?;
+            writeln!(h, "int disc;")?;
+            writeln!(h, "union {{")?;
+
+            for (i, variant) in e.variants.iter().enumerate() {
+                writeln!(h, "struct {} v{i};", module.type_name(variant))?;
+            }
+
+            writeln!(h, "}} *inner;")?;
+            writeln!(h, "}};\n")?;
+
+            // Printed as the active variant's index and its payload, like `[1: ((),)]`
+            let mut printer = vec![
+                "printf(\"[%d: \", ptr->disc);".to_string(),
+                "switch (ptr->disc) {".to_string(),
+            ];
+            for (i, variant) in e.variants.iter().enumerate() {
+                let v_name = module.type_name(variant);
+                printer.push(format!(
+                    "case {i}: print_{v_name}(&ptr->inner->v{i}); break;"
+                ));
+            }
+            printer.push("}".to_string());
+            printer.push("printf(\"]\");".to_string());
+
+            printer
+        }
+        Type::Named(_) => unreachable!(),
+    };
+
+    // Create the allocation function for the type
+    match module.opts.strategy {
+        Strategy::RefCount if module.opts.debug_rc() => {
+            writeln!(
+                h,
+                "struct {name} *allocate_{name}(const char *site, struct {name} val) {{"
+            )?;
+            writeln!(h, "struct {name} *a = rc_alloc(site, sizeof(val));")?;
+        }
+        Strategy::RefCount if module.opts.pooled() => {
+            writeln!(
+                h,
+                "static struct rc_pool pool_{name} = {{RC_POOL_CELL(sizeof(struct {name})), NULL}};"
+            )?;
+            writeln!(h, "struct {name} *allocate_{name}(struct {name} val) {{")?;
+            writeln!(h, "struct {name} *a = rc_pool_alloc(&pool_{name});")?;
+        }
+        Strategy::RefCount => {
+            writeln!(h, "struct {name} *allocate_{name}(struct {name} val) {{")?;
+            writeln!(h, "struct {name} *a = rc_alloc(sizeof(val));")?;
+        }
+        Strategy::Region => {
+            writeln!(
+                h,
+                "struct {name} *allocate_{name}(struct region *rgn, struct {name} val) {{"
+            )?;
+            writeln!(h, "struct {name} *a = region_alloc(rgn, sizeof(val));")?;
+        }
+    }
+    writeln!(h, "*a = val;")?;
+    writeln!(h, "return a;")?;
+    writeln!(h, "}}")?;
+
+    if module.opts.strategy == Strategy::RefCount {
+        let free = match module.opts.pooled() {
+            true => format!("rc_pool_free(&pool_{name}, ptr);"),
+            false => format!("rc_free(ptr, sizeof(struct {name}));"),
+        };
+
+        for atomic in rc_variants(module) {
+            // Releasing a cell gives up the references it holds to its children
+            writeln!(h, "void release{atomic}_{name}(struct {name} *ptr) {{")?;
+            for child in release_body(ty, module, &format!("drop{atomic}")) {
+                writeln!(h, "{child}")?;
+            }
+            writeln!(h, "}}")?;
+
+            writeln!(
+                h,
+                "void dup{atomic}_{name}(struct {name} *ptr, int count) {{"
+            )?;
+            writeln!(h, "rc_dup{atomic}(ptr, count);")?;
+            writeln!(h, "}}")?;
+
+            writeln!(
+                h,
+                "void drop{atomic}_{name}(struct {name} *ptr, int count) {{"
+            )?;
+            writeln!(h, "if (rc_drop{atomic}(ptr, count)) {{")?;
+            writeln!(h, "release{atomic}_{name}(ptr);")?;
+            writeln!(h, "{free}")?;
+            writeln!(h, "}}")?;
+            writeln!(h, "}}")?;
+
+            writeln!(h, "void deallocate{atomic}_{name}(struct {name} *ptr) {{")?;
+            writeln!(h, "release{atomic}_{name}(ptr);")?;
+            writeln!(h, "{free}")?;
+            writeln!(h, "}}")?;
+        }
+    }
+
+    writeln!(h, "void print_{name}(struct {name} *ptr) {{")?;
+    for line in printer {
+        writeln!(h, "{line}")?;
+    }
+    writeln!(h, "}}")?;
+
+    Ok(())
+}
+
+// Lets go of what the stack value `value` holds on the heap
+fn release_lines(module: &Module, value: &str, ty: &Type, releases: &[Release]) -> Vec<String> {
+    let mut lines = vec![];
+    for release in releases {
+        match release {
+            Release::Drop { elem, ty, atomic } => {
+                let atomic = if *atomic { "_atomic" } else { "" };
+                let elem_ty = module.type_name(ty);
+                lines.push(format!("drop{atomic}_{elem_ty}({value}.e{elem}, 1);"));
+            }
+            Release::Follow { elem, ty, inner } => {
+                let elem_value = format!("(*{value}.e{elem})");
+                lines.extend(release_lines(module, &elem_value, ty, inner));
+            }
+            Release::Payload { atomic } => {
+                let atomic = if *atomic { "_atomic" } else { "" };
+                let name = module.type_name(ty);
+                lines.push(format!("release{atomic}_{name}(&{value});"));
+            }
+            Release::Variants(variants) => {
+                lines.push(format!("switch ({value}.disc) {{"));
+                for (i, variant, inner) in variants {
+                    let payload = format!("{value}.inner->v{i}");
+                    let drops = release_lines(module, &payload, variant, inner);
+                    lines.push(format!("case {i}: {} break;", drops.join(" ")));
+                }
+                lines.push("}".to_string());
+            }
+        }
+    }
+
+    lines
+}
+
+// Drops the children of a cell. Elements of a heap tuple are on the heap too, and the
+// payload of an enum is its own cell, typed by whichever variant is active.
+fn release_body(ty: &Type, module: &Module, drop: &str) -> Vec<String> {
+    match module.resolve(ty) {
+        Type::Tuple(t) => t
+            .elems
+            .iter()
+            .enumerate()
+            .map(|(i, elem)| format!("{drop}_{}(ptr->e{i}, 1);", module.type_name(elem)))
+            .collect(),
+        Type::Enum(e) => {
+            let mut lines = vec!["switch (ptr->disc) {".to_string()];
+            for (i, variant) in e.variants.iter().enumerate() {
+                let v_name = module.type_name(variant);
+                lines.push(format!(
+                    "case {i}: {drop}_{v_name}(&ptr->inner->v{i}, 1); break;"
+                ));
+            }
+            lines.push("}".to_string());
+            lines
+        }
+        Type::Named(_) => unreachable!(),
+    }
+}
+
+// The suffixes of the reference counting functions every type gets. A cell another thread
+// may hold gives up its children atomically too.
+fn rc_variants(module: &Module) -> &'static [&'static str] {
+    match module.opts.atomic() {
+        true => &["", "_atomic"],
+        false => &[""],
+    }
+}
+
+// Recursive types drop and print each other, so every type's functions are declared up front
+fn declare_type_fns(h: &mut impl io::Write, name: &str, module: &Module) -> io::Result<()> {
+    writeln!(h, "struct {name};")?;
+    writeln!(h, "void print_{name}(struct {name} *ptr);")?;
+
+    if module.opts.strategy == Strategy::RefCount {
+        for atomic in rc_variants(module) {
+            writeln!(h, "void dup{atomic}_{name}(struct {name} *ptr, int count);")?;
+            writeln!(
+                h,
+                "void drop{atomic}_{name}(struct {name} *ptr, int count);"
+            )?;
+            writeln!(h, "void deallocate{atomic}_{name}(struct {name} *ptr);")?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::{Command, Output};
+
+    use super::*;
+    use crate::{
+        backend::{compile_module, Options, Source},
+        cfg::{
+            analysis::{policy::AllocPolicy, Context},
+            test_util::{context, manage, managed_module},
+        },
+    };
+
+    #[test]
+    fn test_entry_point() {
+        let (fns, type_map) = managed_module(include_str!("../../inputs/main.rs"));
+        let dir = std::env::temp_dir().join("perm-mem-test-entry-point");
+
+        let opts = Options {
+            entry: Some("main".into()),
+            ..Options::default()
+        };
+        let mut backend = CBackend::create(&dir).unwrap();
+        compile_module(&mut backend, &fns, &type_map, opts).unwrap();
+
+        let program = fs::read_to_string(dir.join("program.c")).unwrap();
+        assert!(program.contains("struct tuple2_unit_List result = P_main(&b0);"));
+        assert!(program.contains("print_tuple2_unit_List(&result);"));
+        assert!(program.contains("drop_List(result.e1, 1);"));
+        let makefile = fs::read_to_string(dir.join("Makefile")).unwrap();
+        assert!(makefile.starts_with("TARGET = program\n"));
+
+        // the entry point has nothing to pass arguments with
+        let opts = Options {
+            entry: Some("generate".into()),
+            ..Options::default()
+        };
+        let mut backend = CBackend::create(&dir).unwrap();
+        assert!(compile_module(&mut backend, &fns, &type_map, opts).is_err());
+    }
+
+    #[test]
+    fn test_print_builtin() {
+        let (fns, type_map) = managed_module(include_str!("../../inputs/print.rs"));
+        let dir = std::env::temp_dir().join("perm-mem-test-print-builtin");
+
+        let opts = Options {
+            entry: Some("main".into()),
+            ..Options::default()
+        };
+        let mut backend = CBackend::create(&dir).unwrap();
+        compile_module(&mut backend, &fns, &type_map, opts).unwrap();
+
+        let program = fs::read_to_string(dir.join("program.c")).unwrap();
+        let printed = "print_List(&r1_list);\nprintf(\" \");\nprint_tuple2_List_List(&r2_pair);\n";
+        assert!(program.contains(printed));
+        assert!(!program.contains("print_unit(&result);"));
+
+        let types = fs::read_to_string(dir.join("types.h")).unwrap();
+        assert!(types.contains("case 1: print_tuple2_unit_List(&ptr->inner->v1); break;"));
+    }
+
+    #[test]
+    fn test_large_tuples() {
+        let elems = ["a"; 12].join(", ");
+        let src = format!(
+            "fn main() -> ({}) {{
+                let a = invent();
+                tuple({elems})
+            }}",
+            ["()"; 12].join(", ")
+        );
+        let (fns, type_map) = managed_module(&src);
+        let dir = std::env::temp_dir().join("perm-mem-test-large-tuples");
+
+        let mut backend = CBackend::create(&dir).unwrap();
+        compile_module(&mut backend, &fns, &type_map, Options::default()).unwrap();
+
+        let program = fs::read_to_string(dir.join("program.c")).unwrap();
+        assert!(program.contains(" .e11 = r1_a }"));
+        assert!(!program.contains("tuple_base"));
+    }
+
+    #[test]
+    fn test_allocator_options() {
+        let (fns, type_map) = managed_module(include_str!("../../inputs/gen_list.rs"));
+        let dir = std::env::temp_dir().join("perm-mem-test-allocator-options");
+
+        let opts = Options {
+            alloc_hooks: true,
+            pools: true,
+            ..Options::default()
+        };
+        let mut backend = CBackend::create(&dir).unwrap();
+        compile_module(&mut backend, &fns, &type_map, opts).unwrap();
+
+        let types = fs::read_to_string(dir.join("types.h")).unwrap();
+        assert!(types.contains("#define ALLOC_HOOKS"));
+        assert!(types.contains("struct List *a = rc_pool_alloc(&pool_List);"));
+        assert!(types.contains("rc_pool_free(&pool_List, ptr);"));
+
+        // the debug runtime keeps track of every cell itself
+        let opts = Options {
+            debug_runtime: true,
+            pools: true,
+            ..Options::default()
+        };
+        let mut backend = CBackend::create(&dir).unwrap();
+        compile_module(&mut backend, &fns, &type_map, opts).unwrap();
+
+        let types = fs::read_to_string(dir.join("types.h")).unwrap();
+        assert!(!types.contains("pool_List"));
+        assert!(types.contains("rc_free(ptr, sizeof(struct List));"));
+    }
+
+    #[test]
+    fn test_thread_boundaries() {
+        let (fns, type_map) = managed_module(include_str!("../../inputs/threads.rs"));
+        let dir = std::env::temp_dir().join("perm-mem-test-thread-boundaries");
+
+        let opts = Options {
+            entry: Some("main".into()),
+            thread_boundaries: vec!["spawn".into()],
+            pools: true,
+            ..Options::default()
+        };
+        let mut backend = CBackend::create(&dir).unwrap();
+        compile_module(&mut backend, &fns, &type_map, opts).unwrap();
+
+        // only the list handed to `spawn` pays for atomics
+        let program = fs::read_to_string(dir.join("program.c")).unwrap();
+        assert!(program.contains("deallocate_atomic_List(r3_sent);"));
+        assert!(program.contains("drop_List(result.e1, 1);"));
+
+        let types = fs::read_to_string(dir.join("types.h")).unwrap();
+        assert!(types.contains("#define RC_ATOMIC"));
+        assert!(types.contains("case 1: drop_atomic_tuple2_unit_List(&ptr->inner->v1, 1); break;"));
+        assert!(!types.contains("pool_List"));
+    }
+
+    #[test]
+    fn test_line_directives() {
+        let src = include_str!("../../inputs/threads.rs");
+        let (fns, type_map) = managed_module(src);
+        let dir = std::env::temp_dir().join("perm-mem-test-line-directives");
+
+        let opts = Options {
+            source: Some(Source::new("threads.rs", src.to_string())),
+            ..Options::default()
+        };
+        let mut backend = CBackend::create(&dir).unwrap();
+        compile_module(&mut backend, &fns, &type_map, opts).unwrap();
+
+        let program = fs::read_to_string(dir.join("program.c")).unwrap();
+        let sent =
+            "// let sent = generate(a);\n#line 22 \"threads.rs\"\nr3_sent = P_generate(&r1_a);\n";
+        assert!(program.contains(sent));
+
+        // the code after a function is attributed to the C again
+        let lines: Vec<_> = program.lines().collect();
+        let (i, _) = lines
+            .iter()
+            .enumerate()
+            .find(|(_, l)| l.ends_with("\"program.c\""))
+            .unwrap();
+        assert_eq!(lines[i], format!("#line {} \"program.c\"", i + 2));
+    }
+
+    // Builds the module into an executable running `main`, along with any host code, and
+    // runs it
+    fn run(name: &str, ctx: Context, opts: Options, host: Option<&str>) -> Output {
+        let (fns, type_map) = manage(ctx);
+        let dir = std::env::temp_dir().join(format!("perm-mem-test-{name}"));
+
+        let opts = Options {
+            entry: Some("main".into()),
+            ..opts
+        };
+        let mut backend = CBackend::create(&dir).unwrap();
+        compile_module(&mut backend, &fns, &type_map, opts).unwrap();
+
+        let mut sources = vec!["program.c"];
+        if let Some(host) = host {
+            fs::write(dir.join("host.c"), host).unwrap();
+            sources.push("host.c");
+        }
+
+        let built = Command::new("cc")
+            .current_dir(&dir)
+            .arg("-std=c11")
+            .args(sources)
+            .args(["-o", "program"])
+            .status()
+            .expect("running the generated programs needs a C compiler");
+        assert!(built.success());
+
+        let run = Command::new(dir.join("program")).output().unwrap();
+        assert!(run.status.success());
+        run
+    }
+
+    // Runs the module with the counting runtime, giving back its report
+    fn run_counted(name: &str, ctx: Context) -> String {
+        let opts = Options {
+            debug_runtime: true,
+            ..Options::default()
+        };
+        let run = run(name, ctx, opts, None);
+        String::from_utf8(run.stderr).unwrap()
+    }
+
+    #[test]
+    fn test_report_blames_no_site() {
+        let src = "type List = [() | ((), List)];\n\
+                   fn cons(c: ()) -> List {\n\
+                       if c { List(tuple(tuple(), List(tuple()))) } else { List(tuple(tuple(), cons(c))) }\n\
+                   }\n\
+                   fn main() -> () { let c = invent(); let l = cons(c); print(l) }";
+        let report = run_counted("report-sites", context(src));
+
+        // every cell of both variants is counted where it was allocated, and freed
+        let sites: Vec<Vec<_>> = report
+            .lines()
+            .filter(|l| l.starts_with("rc: cons:"))
+            .map(|l| l.split_whitespace().skip(1).collect())
+            .collect();
+        assert_eq!(sites.len(), 5, "{report}");
+        for site in sites {
+            assert_eq!(site[1..], ["1", "1", "0"], "{report}");
+        }
+        assert!(report.contains("rc: 0 leaked, 0 double frees, 0 negative counts"));
+    }
+
+    // An allocator for `set_allocator` that checks every free is told the size that was
+    // allocated, installed before the program starts
+    const CHECKED_ALLOCATOR: &str = r#"
+#include <stdio.h>
+#include "alloc.h"
+
+void set_allocator(struct allocator allocator);
+
+static void *ptrs[1024];
+static size_t sizes[1024];
+static int allocs, frees, wrong;
+
+static void *checked_alloc(void *ctx, size_t size) {
+    (void) ctx;
+    ptrs[allocs] = malloc(size);
+    sizes[allocs] = size;
+    return ptrs[allocs++];
+}
+
+static void checked_free(void *ctx, void *ptr, size_t size) {
+    (void) ctx;
+    for (int i = 0; i < allocs; i++) {
+        if (ptrs[i] == ptr) {
+            wrong += sizes[i] != size;
+            ptrs[i] = NULL;
+        }
+    }
+    frees++;
+    free(ptr);
+}
+
+static void report(void) {
+    fprintf(stderr, "host: %d allocs, %d frees, %d wrong sizes\n", allocs, frees, wrong);
+}
+
+__attribute__((constructor)) static void install(void) {
+    set_allocator((struct allocator) {checked_alloc, checked_free, NULL});
+    atexit(report);
+}
+"#;
+
+    #[test]
+    fn test_allocators_with_variants() {
+        let src = "type List = [() | ((), List)];\n\
+                   fn cons(c: ()) -> List {\n\
+                       if c { List(tuple(tuple(), List(tuple()))) } else { List(tuple(tuple(), cons(c))) }\n\
+                   }\n\
+                   fn main() -> () { let c = invent(); let l = cons(c); print(l) }";
+
+        // frees are handed back the size of the variant that was built
+        let opts = Options {
+            alloc_hooks: true,
+            ..Options::default()
+        };
+        let hooked = run("hooked-variants", context(src), opts, Some(CHECKED_ALLOCATOR));
+        let report = String::from_utf8(hooked.stderr).unwrap();
+        assert!(report.contains("host: 5 allocs, 5 frees, 0 wrong sizes"), "{report}");
+
+        // pooling takes one slab for each type of cell and still builds the right variants
+        let opts = Options {
+            alloc_hooks: true,
+            pools: true,
+            ..Options::default()
+        };
+        let pooled = run("pooled-variants", context(src), opts, Some(CHECKED_ALLOCATOR));
+        assert_eq!(String::from_utf8(pooled.stdout).unwrap(), "[1: ((), [0: ()])]\n");
+        let report = String::from_utf8(pooled.stderr).unwrap();
+        assert!(report.contains("host: 3 allocs, 0 frees"), "{report}");
+    }
+
+    #[test]
+    fn test_branch_on_locals() {
+        let src = "type List = [() | ((), List)];\n\
+                   fn main() -> () {\n\
+                       let c = invent();\n\
+                       let e = List(tuple());\n\
+                       let f = List(tuple(c, e));\n\
+                       let a = if c { c } else { invent() };\n\
+                       let b = if e { print(e) } else { print(a) };\n\
+                       if f { print(f) } else { print(c) }\n\
+                   }";
+        let run = run("branch-on-locals", context(src), Options::default(), None);
+        assert_eq!(String::from_utf8(run.stdout).unwrap(), "()\n[1: ((), [0: ()])]\n");
+
+        // branches on the results of calls compile too
+        let (fns, type_map) = managed_module(include_str!("../../inputs/factorial.rs"));
+        let dir = std::env::temp_dir().join("perm-mem-test-factorial");
+        let mut backend = CBackend::create(&dir).unwrap();
+        compile_module(&mut backend, &fns, &type_map, Options::default()).unwrap();
+        let built = Command::new("cc")
+            .current_dir(&dir)
+            .args(["-std=c11", "-c", "program.c", "-o", "program.o"])
+            .output()
+            .expect("running the generated programs needs a C compiler");
+        assert!(built.status.success(), "{}", String::from_utf8_lossy(&built.stderr));
+    }
+
+    #[test]
+    fn test_printed_output() {
+        let cases = [
+            (
+                "print-single",
+                include_str!("../../inputs/print.rs").to_string(),
+                "[0: ()] ([0: ()], [0: ()])\n",
+            ),
+            (
+                "print-variants",
+                "type List = [() | ((), List)];\n\
+                 fn main() -> List { List(tuple(tuple(), List(tuple(tuple(), List(tuple()))))) }"
+                    .to_string(),
+                "[1: ((), [1: ((), [0: ()])])]\n",
+            ),
+        ];
+
+        for (name, src, expected) in cases {
+            let run = run(name, context(&src), Options::default(), None);
+            assert_eq!(String::from_utf8(run.stdout).unwrap(), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn test_regions() {
+        let src = format!(
+            "{}\nfn run(c: ()) -> List {{ let l = generate(c); let u = print(l); generate(c) }}\n\
+             fn main() -> () {{ let c = invent(); let l = run(c); print(l) }}",
+            include_str!("../../inputs/gen_list.rs")
+        );
+        let mut ctx = context(&src);
+        ctx.strategy = Strategy::Region;
+        let opts = Options {
+            strategy: Strategy::Region,
+            alloc_hooks: true,
+            ..Options::default()
+        };
+        let run = run("regions", ctx, opts, Some(CHECKED_ALLOCATOR));
+        assert_eq!(String::from_utf8(run.stdout).unwrap(), "[0: ()]\n[0: ()]\n");
+
+        // each list is built in the region of the call that's done with it
+        let program = fs::read_to_string(std::env::temp_dir().join("perm-mem-test-regions/program.c"))
+            .unwrap();
+        let run_body = &program[program.find("P_run(struct unit *r1_c").unwrap()..];
+        let printed = run_body.find("P_generate(r1_c, &local);").unwrap();
+        let returned = run_body.find("P_generate(r1_c, rgn);").unwrap();
+        assert!(printed < returned);
+        assert!(program.contains("r2_l = P_run(&r1_c, &local);"));
+        assert!(program.contains("allocate_List(rgn, P_List(0, r2));"));
+
+        // and every region gives back all of its chunks
+        let report = String::from_utf8(run.stderr).unwrap();
+        let counts: Vec<usize> = report
+            .trim_start_matches("host: ")
+            .split(", ")
+            .map(|c| c.split(' ').next().unwrap().parse().unwrap())
+            .collect();
+        assert!(counts[0] > 0, "{report}");
+        assert_eq!(counts[1], counts[0], "{report}");
+        assert_eq!(counts[2], 0, "{report}");
+    }
+
+    #[test]
+    fn test_runtime_frees_everything() {
+        let gen_list = include_str!("../../inputs/gen_list.rs");
+        let cases = [
+            ("returned", include_str!("../../inputs/main.rs").to_string(), AllocPolicy::default()),
+            (
+                "held",
+                format!(
+                    "{gen_list}\nfn twice(c: ()) -> (List, List) {{ let l = generate(c); tuple(l, l) }}\n\
+                     fn run(c: ()) -> () {{ let t = twice(c); print(t) }}\n\
+                     fn main() -> () {{ let c = invent(); run(c) }}"
+                ),
+                AllocPolicy::default(),
+            ),
+            (
+                "repeated",
+                format!(
+                    "{gen_list}\nfn run(c: ()) -> (List, List) {{ let l = generate(c); tuple(l, l) }}\n\
+                     fn main() -> (List, List) {{ let c = invent(); run(c) }}"
+                ),
+                AllocPolicy::AlwaysHeap,
+            ),
+            (
+                "variants",
+                "type List = [() | ((), List)];\n\
+                 fn cons(c: ()) -> List {\n\
+                     if c { List(tuple(tuple(), List(tuple()))) } else { List(tuple(tuple(), cons(c))) }\n\
+                 }\n\
+                 fn main() -> () { let c = invent(); let l = cons(c); print(l) }"
+                    .to_string(),
+                AllocPolicy::default(),
+            ),
+            (
+                "parts",
+                format!(
+                    "{gen_list}\nfn pair(c: ()) -> ((), List) {{ tuple(c, generate(c)) }}\n\
+                     fn run(c: ()) -> () {{ let p = pair(c); let s = pair(c); let h = tuple(p, s); let u = print(h); print(p) }}\n\
+                     fn main() -> () {{ let c = invent(); run(c) }}"
+                ),
+                AllocPolicy::default(),
+            ),
+        ];
+
+        for (name, src, policy) in cases {
+            let mut ctx = context(&src);
+            ctx.alloc_policy = policy;
+
+            let report = run_counted(name, ctx);
+            assert!(
+                report.contains("rc: 0 leaked, 0 double frees, 0 negative counts"),
+                "{name}: {report}"
+            );
+        }
+    }
+}
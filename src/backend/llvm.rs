@@ -0,0 +1,833 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    io::Write,
+    path::Path,
+};
+
+use super::{Backend, Function, Module, Release};
+use crate::{
+    cfg::{mem_manage::Strategy, Statement, Terminator, Value},
+    types::Type,
+};
+
+// Every reference counted cell is preceded by an i64 count
+const HEADER_SIZE: usize = 8;
+
+/// Writes the module as textual LLVM IR to `program.ll`. Every place is represented by a
+/// pointer to its value: stack places point at an `alloca`, allocated places at a
+/// reference counted cell, and copies alias their source.
+pub struct LlvmBackend {
+    f: fs::File,
+    types: Types,
+    /// The buffers passed to each call of the current function, by the place it assigns
+    buffers: HashMap<usize, Vec<String>>,
+    /// The blocks of the current function that are written, which are the only ones phis
+    /// can come from
+    reachable: HashSet<usize>,
+    /// Functions that let go of what stack enums hold, written at the end
+    helpers: Vec<String>,
+    tmp: usize,
+}
+
+impl LlvmBackend {
+    pub fn create(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+
+        Ok(Self {
+            f: fs::File::create(dir.join("program.ll"))?,
+            types: Types::default(),
+            buffers: HashMap::new(),
+            reachable: HashSet::new(),
+            helpers: vec![],
+            tmp: 0,
+        })
+    }
+
+    fn fresh(&mut self) -> String {
+        self.tmp += 1;
+        format!("%t{}", self.tmp)
+    }
+
+    // Lets go of what the stack value `value` points at holds on the heap. Enums that only
+    // might hold something have to look at their discriminant first, which is done in a
+    // helper function so that the blocks phis come from stay the same.
+    fn release(
+        &mut self,
+        module: &Module,
+        value: &str,
+        ty: &Type,
+        releases: &[Release],
+    ) -> Vec<String> {
+        let llvm_ty = self.types.llvm(module, ty);
+
+        let mut lines = vec![];
+        for release in releases {
+            match release {
+                Release::Drop { elem, ty, .. } | Release::Follow { elem, ty, .. } => {
+                    let field = self.fresh();
+                    let ptr = self.fresh();
+                    lines.push(format!(
+                        "{field} = getelementptr {llvm_ty}, ptr {value}, i32 0, i32 {elem}"
+                    ));
+                    lines.push(format!("{ptr} = load ptr, ptr {field}"));
+
+                    match release {
+                        Release::Follow { inner, .. } => {
+                            lines.extend(self.release(module, &ptr, ty, inner));
+                        }
+                        _ => {
+                            self.types.id(module, ty);
+                            let id = module.type_name(ty);
+                            lines.push(format!("call void @drop.{id}(ptr {ptr}, i32 1)"));
+                        }
+                    }
+                }
+                Release::Payload { .. } => {
+                    let id = module.type_name(ty);
+                    lines.push(format!("call void @release.{id}(ptr {value})"));
+                }
+                Release::Variants(variants) => {
+                    let index = self.helpers.len();
+                    let helper = format!("@release.held.{index}");
+                    self.helpers.push(String::new());
+
+                    let mut body = vec![
+                        format!("define private void {helper}(ptr %value) {{"),
+                        format!("%fd = getelementptr {llvm_ty}, ptr %value, i32 0, i32 0"),
+                        "%disc = load i32, ptr %fd".to_string(),
+                        format!("%fi = getelementptr {llvm_ty}, ptr %value, i32 0, i32 1"),
+                        "%inner = load ptr, ptr %fi".to_string(),
+                    ];
+                    let cases: Vec<_> = variants
+                        .iter()
+                        .map(|(i, _, _)| format!("i32 {i}, label %v{i}"))
+                        .collect();
+                    body.push(format!(
+                        "switch i32 %disc, label %done [ {} ]",
+                        cases.join(" ")
+                    ));
+                    for (i, variant, inner) in variants {
+                        body.push(format!("v{i}:"));
+                        body.extend(self.release(module, "%inner", variant, inner));
+                        body.push("br label %done".to_string());
+                    }
+                    body.extend(["done:".to_string(), "ret void".to_string(), "}\n".to_string()]);
+
+                    self.helpers[index] = body.join("\n");
+                    lines.push(format!("call void {helper}(ptr {value})"));
+                }
+            }
+        }
+
+        lines
+    }
+
+    fn ret_ty(&mut self, module: &Module, func: &Function) -> String {
+        match func.returns_ptr() {
+            true => "ptr".to_string(),
+            false => self.types.llvm(module, &func.cfg.place_tys[0]),
+        }
+    }
+
+    // Runs the entry function, prints what it returns unless that's unit, and then lets go
+    // of it, like the C backend
+    fn compile_entry(&mut self, module: &Module, func: &Function) -> io::Result<()> {
+        let cfg = func.cfg;
+        let ret_ty = self.ret_ty(module, func);
+        let name = module.type_name(&cfg.place_tys[0]);
+
+        writeln!(self.f, "define i32 @main() {{")?;
+        let mut args = vec![];
+        for (i, new) in func.arged_new.iter().enumerate() {
+            let ty = self.types.llvm(module, &cfg.place_tys[*new]);
+            writeln!(self.f, "%b{i} = alloca {ty}")?;
+            args.push(format!("ptr %b{i}"));
+        }
+
+        writeln!(
+            self.f,
+            "%result = call {ret_ty} @P_{}({})",
+            cfg.name,
+            args.join(", ")
+        )?;
+        let value = match func.returns_ptr() {
+            true => "%result",
+            false => {
+                writeln!(self.f, "%value = alloca {ret_ty}")?;
+                writeln!(self.f, "store {ret_ty} %result, ptr %value")?;
+                "%value"
+            }
+        };
+
+        if module.resolve(&cfg.place_tys[0]) != &Type::unit() {
+            writeln!(self.f, "call void @print.{name}(ptr {value})")?;
+            writeln!(self.f, "{}", printf("\\0A"))?;
+        }
+
+        if func.returns_ptr() {
+            writeln!(self.f, "call void @drop.{name}(ptr %result, i32 1)")?;
+        } else {
+            // A result returned by value still holds references to whatever it points
+            // to on the heap
+            self.tmp = 0;
+            let releases = module.releases(func, 0);
+            for line in self.release(module, value, &cfg.place_tys[0], &releases) {
+                writeln!(self.f, "{line}")?;
+            }
+        }
+        writeln!(self.f, "ret i32 0")?;
+        writeln!(self.f, "}}\n")
+    }
+}
+
+// Collects each distinct type, by the name its structure gets
+#[derive(Default)]
+struct Types {
+    tys: Vec<Type>,
+    ids: HashMap<String, usize>,
+}
+
+impl Types {
+    fn id(&mut self, module: &Module, ty: &Type) -> usize {
+        let name = module.type_name(ty);
+        if let Some(&id) = self.ids.get(&name) {
+            return id;
+        }
+
+        self.tys.push(module.resolve(ty).clone());
+        self.ids.insert(name, self.tys.len() - 1);
+        self.tys.len() - 1
+    }
+
+    // Tuples hold a pointer to each element, and enums a discriminant and a pointer to
+    // their payload
+    fn llvm(&mut self, module: &Module, ty: &Type) -> String {
+        let id = self.id(module, ty);
+        match &self.tys[id] {
+            Type::Tuple(t) if t.elems.is_empty() => "{}".to_string(),
+            Type::Tuple(t) => format!("{{ {} }}", vec!["ptr"; t.elems.len()].join(", ")),
+            Type::Enum(_) => "{ i32, ptr }".to_string(),
+            Type::Named(_) => unreachable!(),
+        }
+    }
+}
+
+impl Backend for LlvmBackend {
+    fn prologue(&mut self, module: &Module) -> io::Result<()> {
+        if module.opts.strategy != Strategy::RefCount {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the LLVM backend only supports reference counting",
+            ));
+        }
+        if module.opts.alloc_hooks || module.opts.pools {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the LLVM backend always allocates with malloc",
+            ));
+        }
+        if module.opts.atomic() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the LLVM backend doesn't update reference counts atomically",
+            ));
+        }
+
+        Ok(())
+    }
+
+    // The runtime functions are written at the end, once every type they reach is known
+    fn declare_types(&mut self, module: &Module, tys: &[&Type]) -> io::Result<()> {
+        for ty in tys {
+            self.types.id(module, ty);
+        }
+
+        Ok(())
+    }
+
+    fn signature(&mut self, module: &Module, func: &Function) -> io::Result<()> {
+        let cfg = func.cfg;
+        let ret_ty = self.ret_ty(module, func);
+
+        let params: Vec<_> = (1..=cfg.arg_count)
+            .chain(func.arged_new.iter().copied())
+            .map(|p| format!("ptr %r{p}"))
+            .collect();
+        writeln!(
+            self.f,
+            "define {ret_ty} @P_{}({}) {{",
+            cfg.name,
+            params.join(", ")
+        )?;
+
+        // Stack places get their storage up front, along with buffers for the new lives
+        // of the functions that are called
+        writeln!(self.f, "entry:")?;
+        self.buffers.clear();
+        self.tmp = 0;
+
+        self.reachable = HashSet::from([0]);
+        let mut stack = vec![0];
+        while let Some(b) = stack.pop() {
+            for succ in cfg.successors(b) {
+                if self.reachable.insert(succ) {
+                    stack.push(succ);
+                }
+            }
+        }
+
+        for stmnt in cfg.statements() {
+            let Statement::Assign(a) = stmnt else {
+                continue;
+            };
+
+            let Value::Call { func: callee, .. } = &a.value else {
+                continue;
+            };
+
+            let returns_ptr = module
+                .get_fn(callee)
+                .map(|f| f.returns_ptr())
+                .unwrap_or(false);
+            if !a.allocate && !returns_ptr && !func.arged_new.contains(&a.place) {
+                let ty = self.types.llvm(module, &cfg.place_tys[a.place]);
+                writeln!(self.f, "%r{} = alloca {ty}", a.place)?;
+            }
+
+            let mut names = vec![];
+            for (i, new_ty) in module.call_buffers(callee).into_iter().enumerate() {
+                let ty = self.types.llvm(module, new_ty);
+                writeln!(self.f, "%r{}n{i} = alloca {ty}", a.place)?;
+                names.push(format!("ptr %r{}n{i}", a.place));
+            }
+            self.buffers.insert(a.place, names);
+        }
+        writeln!(self.f, "br label %L0")
+    }
+
+    fn block(&mut self, _module: &Module, func: &Function, block: usize) -> io::Result<()> {
+        writeln!(self.f, "L{block}:")?;
+
+        for phi in &func.cfg.basic_blocks[block].phi {
+            let mut opts: Vec<_> = phi.opts.iter().collect();
+            opts.sort();
+
+            let incoming: Vec<_> = opts
+                .into_iter()
+                .filter(|(pred, _)| self.reachable.contains(pred))
+                .map(|(pred, alt)| format!("[ %r{alt}, %L{pred} ]"))
+                .collect();
+            writeln!(self.f, "%r{} = phi ptr {}", phi.place, incoming.join(", "))?;
+        }
+
+        Ok(())
+    }
+
+    fn statement(
+        &mut self,
+        module: &Module,
+        func: &Function,
+        _point: (usize, usize),
+        stmnt: &Statement,
+    ) -> io::Result<()> {
+        let cfg = func.cfg;
+
+        match stmnt {
+            Statement::Assign(a) => {
+                let id = module.type_name(&cfg.place_tys[a.place]);
+                let ty = self.types.llvm(module, &cfg.place_tys[a.place]);
+
+                // Copies alias their source unless they're moving it onto the heap
+                if let Value::Place(src) = &a.value {
+                    if a.allocate {
+                        let val = self.fresh();
+                        writeln!(self.f, "%r{} = call ptr @allocate.{id}()", a.place)?;
+                        writeln!(self.f, "{val} = load {ty}, ptr %r{src}")?;
+                        writeln!(self.f, "store {ty} {val}, ptr %r{}", a.place)?;
+                    } else {
+                        writeln!(
+                            self.f,
+                            "%r{} = getelementptr i8, ptr %r{src}, i64 0",
+                            a.place
+                        )?;
+                    }
+                    return Ok(());
+                }
+
+                let Value::Call { func: callee, args } = &a.value else {
+                    unreachable!()
+                };
+
+                let dest = format!("%r{}", a.place);
+                if a.allocate {
+                    writeln!(self.f, "{dest} = call ptr @allocate.{id}()")?;
+                }
+
+                match callee.0.as_str() {
+                    "invent" => {}
+                    "print" => {
+                        // Prints its arguments on one line separated by spaces, like the C
+                        for (i, arg) in args.iter().enumerate() {
+                            if i != 0 {
+                                writeln!(self.f, "{}", printf(" "))?;
+                            }
+                            self.types.id(module, &cfg.place_tys[*arg]);
+                            let arg_id = module.type_name(&cfg.place_tys[*arg]);
+                            writeln!(self.f, "call void @print.{arg_id}(ptr %r{arg})")?;
+                        }
+                        writeln!(self.f, "{}", printf("\\0A"))?;
+                    }
+                    "tuple" => {
+                        for (i, arg) in args.iter().enumerate() {
+                            let field = self.fresh();
+                            writeln!(
+                                self.f,
+                                "{field} = getelementptr {ty}, ptr {dest}, i32 0, i32 {i}"
+                            )?;
+                            writeln!(self.f, "store ptr %r{arg}, ptr {field}")?;
+                        }
+                    }
+                    name if !module.fns.contains_key(callee) => {
+                        assert!(module.type_map.contains_key(name));
+
+                        let variant = module.variant_of(&cfg.place_tys[a.place], &cfg.place_tys[args[0]]);
+                        let disc = self.fresh();
+                        let inner = self.fresh();
+                        writeln!(
+                            self.f,
+                            "{disc} = getelementptr {ty}, ptr {dest}, i32 0, i32 0"
+                        )?;
+                        writeln!(self.f, "store i32 {variant}, ptr {disc}")?;
+                        writeln!(
+                            self.f,
+                            "{inner} = getelementptr {ty}, ptr {dest}, i32 0, i32 1"
+                        )?;
+                        writeln!(self.f, "store ptr %r{}, ptr {inner}", args[0])?;
+                    }
+                    _ => {
+                        let mut call_args: Vec<_> =
+                            args.iter().map(|a| format!("ptr %r{a}")).collect();
+                        call_args.extend(self.buffers[&a.place].iter().cloned());
+
+                        if module.get_fn(callee).unwrap().returns_ptr() {
+                            writeln!(
+                                self.f,
+                                "{dest} = call ptr @P_{callee}({})",
+                                call_args.join(", ")
+                            )?;
+                        } else {
+                            let val = self.fresh();
+                            writeln!(
+                                self.f,
+                                "{val} = call {ty} @P_{callee}({})",
+                                call_args.join(", ")
+                            )?;
+                            writeln!(self.f, "store {ty} {val}, ptr {dest}")?;
+                        }
+                    }
+                }
+
+                Ok(())
+            }
+            Statement::Deallocate(p) => {
+                let id = module.type_name(&cfg.place_tys[*p]);
+                writeln!(self.f, "call void @deallocate.{id}(ptr %r{p})")
+            }
+            Statement::Dup(rc) => {
+                let id = module.type_name(&cfg.place_tys[rc.place]);
+                writeln!(
+                    self.f,
+                    "call void @dup.{id}(ptr %r{}, i32 {})",
+                    rc.place, rc.count
+                )
+            }
+            Statement::Drop(rc) if !func.deps.heap_stored(rc.place) => {
+                // A stack value gives up the references it holds instead
+                let releases = module.releases(func, rc.place);
+                let value = format!("%r{}", rc.place);
+                for line in self.release(module, &value, &cfg.place_tys[rc.place], &releases) {
+                    writeln!(self.f, "{line}")?;
+                }
+                Ok(())
+            }
+            Statement::Drop(rc) => {
+                let id = module.type_name(&cfg.place_tys[rc.place]);
+                writeln!(
+                    self.f,
+                    "call void @drop.{id}(ptr %r{}, i32 {})",
+                    rc.place, rc.count
+                )
+            }
+            Statement::Nop => Ok(()),
+        }
+    }
+
+    fn terminator(
+        &mut self,
+        module: &Module,
+        func: &Function,
+        _block: usize,
+        term: &Terminator,
+    ) -> io::Result<()> {
+        match term {
+            Terminator::Goto(next) => writeln!(self.f, "br label %L{next}"),
+            Terminator::Return(p) if func.returns_ptr() => writeln!(self.f, "ret ptr %r{p}"),
+            Terminator::Return(p) => {
+                let ret_ty = self.ret_ty(module, func);
+                let val = self.fresh();
+                writeln!(self.f, "{val} = load {ret_ty}, ptr %r{p}")?;
+                writeln!(self.f, "ret {ret_ty} {val}")
+            }
+            // Enums are true unless they're their first variant. Anything else has no
+            // discriminant and is always true, like the pointers the C backend tests.
+            Terminator::IfElse { cond, iff, elsee } => {
+                let cond_ty = &func.cfg.place_tys[*cond];
+                if !matches!(module.resolve(cond_ty), Type::Enum(_)) {
+                    return writeln!(self.f, "br label %L{iff}");
+                }
+
+                let ty = self.types.llvm(module, cond_ty);
+                let (field, disc, test) = (self.fresh(), self.fresh(), self.fresh());
+                writeln!(
+                    self.f,
+                    "{field} = getelementptr {ty}, ptr %r{cond}, i32 0, i32 0"
+                )?;
+                writeln!(self.f, "{disc} = load i32, ptr {field}")?;
+                writeln!(self.f, "{test} = icmp ne i32 {disc}, 0")?;
+                writeln!(self.f, "br i1 {test}, label %L{iff}, label %L{elsee}")
+            }
+        }
+    }
+
+    fn end_function(&mut self, _module: &Module, _func: &Function) -> io::Result<()> {
+        writeln!(self.f, "}}\n")
+    }
+
+    fn epilogue(&mut self, module: &Module) -> io::Result<()> {
+        if let Some(entry) = &module.opts.entry {
+            self.compile_entry(module, &module.get_fn(entry).unwrap())?;
+        }
+
+        // Runtime functions for every type that was used, including the ones only reached
+        // through another type's children
+        let mut id = 0;
+        while id < self.types.tys.len() {
+            compile_rc_fns(&mut self.f, id, &mut self.types, module)?;
+            compile_printer(&mut self.f, id, &mut self.types, module)?;
+            id += 1;
+        }
+
+        for (text, name) in FORMATS {
+            let len = text.replace("\\0A", "\n").len() + 1;
+            writeln!(
+                self.f,
+                "@{name} = private unnamed_addr constant [{len} x i8] c\"{text}\\00\""
+            )?;
+        }
+
+        for helper in &self.helpers {
+            writeln!(self.f, "{helper}")?;
+        }
+
+        writeln!(self.f, "declare ptr @malloc(i64)")?;
+        writeln!(self.f, "declare void @free(ptr)")?;
+        writeln!(self.f, "declare i32 @printf(ptr, ...)")
+    }
+}
+
+// The text printers write, in LLVM's escaping, and the constants they're stored in
+const FORMATS: [(&str, &str); 8] = [
+    ("(", "str.open"),
+    (")", "str.close"),
+    (", ", "str.sep"),
+    (",", "str.comma"),
+    ("[%d: ", "str.variant"),
+    ("]", "str.end"),
+    (" ", "str.space"),
+    ("\\0A", "str.newline"),
+];
+
+// A call to printf with one of the constant texts
+fn printf(text: &str) -> String {
+    let (_, name) = FORMATS.iter().find(|(t, _)| *t == text).unwrap();
+    format!("call i32 (ptr, ...) @printf(ptr @{name})")
+}
+
+// Prints a value the same way the C backend does: tuples like Rust tuples, and enums as
+// the index of their variant and its payload, like `[1: ((),)]`
+fn compile_printer(
+    f: &mut impl io::Write,
+    id: usize,
+    types: &mut Types,
+    module: &Module,
+) -> io::Result<()> {
+    let ty = types.tys[id].clone();
+    let llvm_ty = types.llvm(module, &ty);
+    let id = module.type_name(&ty);
+
+    writeln!(f, "define void @print.{id}(ptr %value) {{")?;
+    match &ty {
+        Type::Tuple(t) => {
+            writeln!(f, "{}", printf("("))?;
+            for (i, elem) in t.elems.iter().enumerate() {
+                if i != 0 {
+                    writeln!(f, "{}", printf(", "))?;
+                }
+                types.id(module, elem);
+                let elem_id = module.type_name(elem);
+                writeln!(
+                    f,
+                    "%f{i} = getelementptr {llvm_ty}, ptr %value, i32 0, i32 {i}"
+                )?;
+                writeln!(f, "%e{i} = load ptr, ptr %f{i}")?;
+                writeln!(f, "call void @print.{elem_id}(ptr %e{i})")?;
+            }
+            if t.elems.len() == 1 {
+                writeln!(f, "{}", printf(","))?;
+            }
+            writeln!(f, "{}", printf(")"))?;
+            writeln!(f, "ret void")?;
+        }
+        Type::Enum(e) => {
+            writeln!(f, "%fd = getelementptr {llvm_ty}, ptr %value, i32 0, i32 0")?;
+            writeln!(f, "%disc = load i32, ptr %fd")?;
+            writeln!(f, "%fi = getelementptr {llvm_ty}, ptr %value, i32 0, i32 1")?;
+            writeln!(f, "%inner = load ptr, ptr %fi")?;
+            writeln!(
+                f,
+                "call i32 (ptr, ...) @printf(ptr @str.variant, i32 %disc)"
+            )?;
+
+            let cases: Vec<_> = (0..e.variants.len())
+                .map(|i| format!("i32 {i}, label %v{i}"))
+                .collect();
+            writeln!(f, "switch i32 %disc, label %done [ {} ]", cases.join(" "))?;
+            for (i, variant) in e.variants.iter().enumerate() {
+                types.id(module, variant);
+                let variant_id = module.type_name(variant);
+                writeln!(f, "v{i}:")?;
+                writeln!(f, "call void @print.{variant_id}(ptr %inner)")?;
+                writeln!(f, "br label %done")?;
+            }
+            writeln!(f, "done:")?;
+            writeln!(f, "{}", printf("]"))?;
+            writeln!(f, "ret void")?;
+        }
+        Type::Named(_) => unreachable!(),
+    }
+    writeln!(f, "}}\n")
+}
+
+// The allocation and reference counting functions of one type
+fn compile_rc_fns(
+    f: &mut impl io::Write,
+    id: usize,
+    types: &mut Types,
+    module: &Module,
+) -> io::Result<()> {
+    let ty = types.tys[id].clone();
+    let llvm_ty = types.llvm(module, &ty);
+    let id = module.type_name(&ty);
+
+    writeln!(f, "define ptr @allocate.{id}() {{")?;
+    writeln!(f, "%end = getelementptr {llvm_ty}, ptr null, i32 1")?;
+    writeln!(f, "%size = ptrtoint ptr %end to i64")?;
+    writeln!(f, "%total = add i64 %size, {HEADER_SIZE}")?;
+    writeln!(f, "%header = call ptr @malloc(i64 %total)")?;
+    writeln!(f, "store i64 1, ptr %header")?;
+    writeln!(
+        f,
+        "%cell = getelementptr i8, ptr %header, i64 {HEADER_SIZE}"
+    )?;
+    writeln!(f, "ret ptr %cell")?;
+    writeln!(f, "}}\n")?;
+
+    writeln!(f, "define void @dup.{id}(ptr %cell, i32 %count) {{")?;
+    writeln!(
+        f,
+        "%header = getelementptr i8, ptr %cell, i64 -{HEADER_SIZE}"
+    )?;
+    writeln!(f, "%old = load i64, ptr %header")?;
+    writeln!(f, "%by = sext i32 %count to i64")?;
+    writeln!(f, "%new = add i64 %old, %by")?;
+    writeln!(f, "store i64 %new, ptr %header")?;
+    writeln!(f, "ret void")?;
+    writeln!(f, "}}\n")?;
+
+    writeln!(f, "define void @drop.{id}(ptr %cell, i32 %count) {{")?;
+    writeln!(
+        f,
+        "%header = getelementptr i8, ptr %cell, i64 -{HEADER_SIZE}"
+    )?;
+    writeln!(f, "%old = load i64, ptr %header")?;
+    writeln!(f, "%by = sext i32 %count to i64")?;
+    writeln!(f, "%new = sub i64 %old, %by")?;
+    writeln!(f, "store i64 %new, ptr %header")?;
+    writeln!(f, "%dead = icmp sle i64 %new, 0")?;
+    writeln!(f, "br i1 %dead, label %free, label %done")?;
+    writeln!(f, "free:")?;
+    writeln!(f, "call void @deallocate.{id}(ptr %cell)")?;
+    writeln!(f, "br label %done")?;
+    writeln!(f, "done:")?;
+    writeln!(f, "ret void")?;
+    writeln!(f, "}}\n")?;
+
+    // Releasing gives up the references the cell holds to its children
+    writeln!(f, "define void @release.{id}(ptr %cell) {{")?;
+    match &ty {
+        Type::Tuple(t) => {
+            for (i, elem) in t.elems.iter().enumerate() {
+                types.id(module, elem);
+                let elem_id = module.type_name(elem);
+                writeln!(
+                    f,
+                    "%f{i} = getelementptr {llvm_ty}, ptr %cell, i32 0, i32 {i}"
+                )?;
+                writeln!(f, "%e{i} = load ptr, ptr %f{i}")?;
+                writeln!(f, "call void @drop.{elem_id}(ptr %e{i}, i32 1)")?;
+            }
+            writeln!(f, "br label %done")?;
+        }
+        Type::Enum(e) => {
+            writeln!(f, "%fd = getelementptr {llvm_ty}, ptr %cell, i32 0, i32 0")?;
+            writeln!(f, "%disc = load i32, ptr %fd")?;
+            writeln!(f, "%fi = getelementptr {llvm_ty}, ptr %cell, i32 0, i32 1")?;
+            writeln!(f, "%inner = load ptr, ptr %fi")?;
+
+            let cases: Vec<_> = (0..e.variants.len())
+                .map(|i| format!("i32 {i}, label %v{i}"))
+                .collect();
+            writeln!(f, "switch i32 %disc, label %done [ {} ]", cases.join(" "))?;
+
+            for (i, variant) in e.variants.iter().enumerate() {
+                types.id(module, variant);
+                let variant_id = module.type_name(variant);
+                writeln!(f, "v{i}:")?;
+                writeln!(f, "call void @drop.{variant_id}(ptr %inner, i32 1)")?;
+                writeln!(f, "br label %done")?;
+            }
+        }
+        Type::Named(_) => unreachable!(),
+    }
+    writeln!(f, "done:")?;
+    writeln!(f, "ret void")?;
+    writeln!(f, "}}\n")?;
+
+    writeln!(f, "define void @deallocate.{id}(ptr %cell) {{")?;
+    writeln!(f, "call void @release.{id}(ptr %cell)")?;
+    writeln!(
+        f,
+        "%header = getelementptr i8, ptr %cell, i64 -{HEADER_SIZE}"
+    )?;
+    writeln!(f, "call void @free(ptr %header)")?;
+    writeln!(f, "ret void")?;
+    writeln!(f, "}}\n")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::{Command, Output};
+
+    use super::*;
+    use crate::{
+        backend::{compile_module, Options},
+        cfg::{test_util::managed_module, BasicBlock},
+    };
+
+    #[test]
+    fn test_branches_and_phis() {
+        let (mut fns, type_map) = managed_module(&format!(
+            "{}\nfn pick(l: List, a: (), b: ()) -> () {{ if l {{ a }} else {{ b }} }}",
+            include_str!("../../inputs/gen_list.rs")
+        ));
+
+        // a block nothing jumps to can still be one of the options of a phi
+        let cfg = &mut fns.get_mut(&"pick".into()).unwrap().0;
+        let join = cfg.basic_blocks.iter().position(|b| !b.phi.is_empty()).unwrap();
+        let dead = cfg.basic_blocks.len();
+        cfg.basic_blocks.push(BasicBlock {
+            phi: vec![],
+            stmnts: vec![],
+            terminator: Some(Terminator::Goto(join)),
+            term_span: None,
+        });
+        cfg.basic_blocks[join].phi[0].opts.insert(dead, 2);
+
+        let dir = std::env::temp_dir().join("perm-mem-test-llvm-branches");
+        let mut backend = LlvmBackend::create(&dir).unwrap();
+        compile_module(&mut backend, &fns, &type_map, Options::default()).unwrap();
+        let program = fs::read_to_string(dir.join("program.ll")).unwrap();
+
+        // lists branch on their variant, while unit has nothing to look at
+        let pick = &program[program.find("@P_pick").unwrap()..];
+        assert!(pick.contains("%t2 = load i32, ptr %t1\n%t3 = icmp ne i32 %t2, 0\n"));
+        assert!(!pick[..pick.find("}").unwrap()].contains(&format!("%L{dead}")));
+        let generate = &program[program.find("@P_generate").unwrap()..];
+        assert!(generate.starts_with("@P_generate(ptr %r1) {\nentry:\nbr label %L0\nL0:\nbr label %L1\n"));
+
+        // llc has to accept it
+        let llc = llvm_tool("llc", &["-o", "/dev/null"], &dir.join("program.ll"));
+        assert!(llc.status.success(), "{}", String::from_utf8_lossy(&llc.stderr));
+    }
+
+    // Runs an LLVM tool on a file, with opaque pointers turned on for versions where they
+    // aren't the default yet
+    fn llvm_tool(tool: &str, args: &[&str], file: &Path) -> Output {
+        let mut out = None;
+        for opaque in [&["-opaque-pointers"][..], &[]] {
+            let run = Command::new(tool)
+                .args(opaque)
+                .args(args)
+                .arg(file)
+                .output()
+                .unwrap_or_else(|_| panic!("checking the generated IR needs `{tool}`"));
+            if run.status.success() {
+                return run;
+            }
+            out = Some(run);
+        }
+        out.unwrap()
+    }
+
+    #[test]
+    fn test_verified_and_run() {
+        let cases = [
+            (
+                "print",
+                include_str!("../../inputs/print.rs").to_string(),
+                "[0: ()] ([0: ()], [0: ()])\n",
+            ),
+            (
+                "variants",
+                "type List = [() | ((), List)];\n\
+                 fn main() -> List { List(tuple(tuple(), List(tuple(tuple(), List(tuple()))))) }"
+                    .to_string(),
+                "[1: ((), [1: ((), [0: ()])])]\n",
+            ),
+        ];
+
+        for (name, src, expected) in cases {
+            let (fns, type_map) = managed_module(&src);
+            let dir = std::env::temp_dir().join(format!("perm-mem-test-llvm-{name}"));
+            let opts = Options {
+                entry: Some("main".into()),
+                ..Options::default()
+            };
+            let mut backend = LlvmBackend::create(&dir).unwrap();
+            compile_module(&mut backend, &fns, &type_map, opts).unwrap();
+            let program = dir.join("program.ll");
+
+            let verify = llvm_tool("opt", &["-passes=verify", "-disable-output"], &program);
+            assert!(verify.status.success(), "{name}: {}", String::from_utf8_lossy(&verify.stderr));
+
+            // it prints the same as the C would
+            let run = llvm_tool("lli", &[], &program);
+            assert!(run.status.success(), "{name}: {}", String::from_utf8_lossy(&run.stderr));
+            assert_eq!(String::from_utf8(run.stdout).unwrap(), expected, "{name}");
+        }
+    }
+}
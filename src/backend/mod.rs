@@ -1,420 +1,671 @@
+pub mod c;
+pub mod llvm;
+mod slots;
+mod structure;
+
 use std::{
-    collections::{hash_map::DefaultHasher, HashMap},
-    fs,
-    hash::{Hash, Hasher},
+    collections::{HashMap, HashSet},
     io,
-    io::Write,
-    path::Path,
+    path::{Path, PathBuf},
 };
 
 use crate::{
     ast::Ident,
     cfg::{
-        analysis::deps::{DepGraph, Deps},
-        Cfg, Statement, Terminator, Value,
+        analysis::{alias::Aliases, deps::DepGraph, threads},
+        mem_manage::Strategy,
+        Cfg, Statement, Terminator,
     },
     types::Type,
 };
 
-const MAKEFILE: &str = include_str!("Makefile");
-const STD_BASE: &str = include_str!("std_base.c");
-
-pub fn compile_module_to_dir<'a>(
-    dir: impl AsRef<Path>,
-    cfgs: HashMap<Ident, (Cfg, DepGraph)>,
-    type_map: &HashMap<String, Type>,
-) -> io::Result<()> {
-    // Create the build directory
-    let dir = dir.as_ref();
-    fs::create_dir_all(dir)?;
+/// Settings that change the generated code but not what the program computes
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Options {
+    pub strategy: Strategy,
+    /// Counts allocations and frees per allocation site, poisons freed cells, and reports
+    /// leaks, double frees and negative counts at exit. Only the reference counting
+    /// runtime is instrumented.
+    pub debug_runtime: bool,
+    /// Routes every allocation and free through an allocator the host can replace with
+    /// `set_allocator`, rather than calling `malloc` and `free` directly.
+    pub alloc_hooks: bool,
+    /// Gives every type a pool of cells that freed cells are returned to, which is refilled
+    /// a slab at a time. Only the reference counting runtime pools, and not when debugging
+    /// or sharing values between threads.
+    pub pools: bool,
+    /// Updates every reference count atomically, so that any value can be shared between
+    /// threads.
+    pub atomic_rc: bool,
+    /// Functions that hand their arguments to another thread. Values that may reach one of
+    /// them have their reference counts updated atomically, and the rest don't.
+    pub thread_boundaries: Vec<Ident>,
+    /// A function without arguments to run when the program starts. With one, the
+    /// program is built into an executable that prints what it returns.
+    pub entry: Option<Ident>,
+    /// The module's source. With it, the C backend points the code it writes back at the
+    /// lines it came from, so debuggers and sanitizers report those instead.
+    pub source: Option<Source>,
+}
 
-    // Create our files
-    let mut type_file = fs::File::create(dir.join("types.h"))?;
-    writeln!(&mut type_file, "#pragma once")?;
-    writeln!(&mut type_file, "#include <stdlib.h>\n")?;
+/// A source file, split into lines.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Source {
+    pub path: PathBuf,
+    text: String,
+    line_starts: Vec<usize>,
+}
 
-    let std_file = fs::File::create(dir.join("std.c"))?;
+impl Source {
+    pub fn new(path: impl Into<PathBuf>, text: String) -> Self {
+        let line_starts = [0]
+            .into_iter()
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+
+        Self {
+            path: path.into(),
+            text,
+            line_starts,
+        }
+    }
 
-    let mut program_file = fs::File::create(dir.join("program.c"))?;
-    let mut header_file = fs::File::create(dir.join("program.h"))?;
-    writeln!(program_file, "#include \"std.c\"")?;
-    writeln!(program_file, "#include \"program.h\"\n")?;
-    writeln!(program_file, "#include \"types.h\"\n")?;
-    writeln!(header_file, "#include \"types.h\"\n")?;
+    /// The line a byte offset is on, counting from one, along with its text.
+    pub fn line(&self, offset: usize) -> (usize, &str) {
+        let line = self.line_starts.partition_point(|&s| s <= offset);
+        let start = self.line_starts[line - 1];
+        let end = self
+            .line_starts
+            .get(line)
+            .map_or(self.text.len(), |e| e - 1);
+        (line, &self.text[start..end])
+    }
+}
 
-    // Compile the program to c
-    let mut used_types = vec![];
-    for (_, (cfg, deps)) in &cfgs {
-        used_types.extend(&cfg.place_tys);
-        compile_cfg(
-            &mut program_file,
-            &mut header_file,
-            cfg,
-            deps,
-            &cfgs,
-            type_map,
-        )?;
+impl Options {
+    fn debug_rc(&self) -> bool {
+        self.debug_runtime && self.strategy == Strategy::RefCount
     }
 
-    // Write the Makefile
-    fs::write(dir.join("Makefile"), MAKEFILE)?;
+    fn pooled(&self) -> bool {
+        self.pools && self.strategy == Strategy::RefCount && !self.debug_runtime && !self.atomic()
+    }
 
-    // Write the standard library
-    write_std_lib(std_file, &mut type_file)?;
+    // Whether any reference count is updated atomically. Pools aren't safe to share between
+    // threads, so they're turned off then.
+    fn atomic(&self) -> bool {
+        (self.atomic_rc || !self.thread_boundaries.is_empty())
+            && self.strategy == Strategy::RefCount
+    }
+}
 
-    // Write the types
-    compile_tys(&mut type_file, used_types, type_map, type_map)?;
-    writeln!(
-        &mut type_file,
-        "typedef struct {} unit;",
-        type_name(&Type::unit(), type_map)
-    )?;
+/// The module being compiled, shared by every hook of a backend.
+pub struct Module<'a> {
+    pub fns: &'a HashMap<Ident, (Cfg, DepGraph)>,
+    pub type_map: &'a HashMap<String, Type>,
+    pub opts: Options,
+    names: TypeNames,
+    /// The places of each function that may be reached from another thread
+    shared: HashMap<Ident, HashSet<usize>>,
+}
 
-    Ok(())
+/// A function being compiled, along with the parts of its calling convention that every
+/// backend has to agree on.
+pub struct Function<'a> {
+    pub cfg: &'a Cfg,
+    pub deps: &'a DepGraph,
+    /// Alias queries over `deps`, shared by everything that compiles the function.
+    pub aliases: Aliases<'a>,
+    /// New lives that aren't returned, which are written into buffers the caller
+    /// provides. They're passed after the arguments, in this order.
+    pub arged_new: Vec<usize>,
 }
 
-pub fn compile_tys<'a>(
-    mut h: impl io::Write,
-    anon_tys: impl IntoIterator<Item = &'a Type>,
-    named_tys: impl IntoIterator<Item = (&'a String, &'a Type)>,
-    type_map: &HashMap<String, Type>,
-) -> io::Result<HashMap<Type, String>> {
-    let mut remap = HashMap::new();
-
-    // Create the types
-    for anon_ty in anon_tys {
-        compile_ty(&mut h, anon_ty, &mut remap, type_map)?;
-    }
-    for (name, ty) in named_tys {
-        compile_ty(&mut h, ty, &mut remap, type_map)?;
-
-        // Create the constructor
-        if let Type::Enum(_) = ty {
-            // let c_name = type_name(ty, type_map);
-            // writeln!(h, "struct {c_name} *P_{name}(int disc, void *inner) {{",)?;
-            // writeln!(
-            //     h,
-            //     "return (struct {c_name}) {{ .disc = disc, .inner = inner }};"
-            // )?;
-            // writeln!(h, "}}")?;
-
-            let c_name = type_name(ty, type_map);
-            writeln!(h, "struct {c_name} P_{name}(void *inner) {{",)?;
-            writeln!(
-                h,
-                "return (struct {c_name}) {{ .disc = 0, .inner = inner }};"
-            )?;
-            writeln!(h, "}}")?;
+impl<'a> Function<'a> {
+    pub fn new(cfg: &'a Cfg, deps: &'a DepGraph) -> Self {
+        Self {
+            cfg,
+            deps,
+            aliases: deps.aliases(),
+            arged_new: arged_new(deps),
         }
     }
 
-    Ok(remap)
+    /// Whether the function returns a pointer to an allocated value.
+    pub fn returns_ptr(&self) -> bool {
+        self.deps.nodes[0].allocated()
+    }
+
+    /// Whether a place is a pointer to a value on the caller's stack, which is the case
+    /// for arguments and caller provided buffers.
+    pub fn is_ref(&self, place: usize) -> bool {
+        (1..=self.cfg.arg_count).contains(&place) || self.arged_new.contains(&place)
+    }
 }
 
-pub fn compile_ty(
-    h: &mut impl io::Write,
-    ty: &Type,
-    remap: &mut HashMap<Type, String>,
-    type_map: &HashMap<String, Type>,
-) -> io::Result<()> {
-    if let Some(_) = remap.get(ty) {
-        return Ok(());
+impl Module<'_> {
+    pub fn get_fn(&self, func: &Ident) -> Option<Function<'_>> {
+        self.fns
+            .get(func)
+            .map(|(cfg, deps)| Function::new(cfg, deps))
     }
 
-    let name = match ty {
-        Type::Tuple(t) => {
-            let name = type_name(ty, type_map);
-            remap.insert(ty.clone(), name.clone());
+    /// The types of the buffers a call to `func` has to pass, in order. Builtins and
+    /// constructors don't take any.
+    pub fn call_buffers(&self, func: &Ident) -> Vec<&Type> {
+        match self.get_fn(func) {
+            Some(f) => f.arged_new.iter().map(|&n| &f.cfg.place_tys[n]).collect(),
+            None => vec![],
+        }
+    }
 
-            for elem in &t.elems {
-                compile_ty(h, elem, remap, type_map)?;
-            }
+    /// Whether the reference count of a place has to be updated atomically.
+    pub fn atomic(&self, func: &Function, place: usize) -> bool {
+        self.opts.atomic()
+            && (self.opts.atomic_rc
+                || self
+                    .shared
+                    .get(&func.cfg.name)
+                    .is_some_and(|s| s.contains(&place)))
+    }
 
-            // Create the tuple type
-            writeln!(h, "// {ty:?}")?;
-            writeln!(h, "struct {name} {{")?;
-            for (i, elem) in t.elems.iter().enumerate() {
-                writeln!(h, "struct {} *e{i};", type_name(elem, type_map))?;
-            }
-            writeln!(h, "}};\n")?;
-
-            // Create the conversion for that tuple type from its untyped version
-            writeln!(
-                h,
-                "struct {name} from_tuple_base{name}(struct tuple_base{} base) {{",
-                t.elems.len()
-            )?;
-            writeln!(h, "return *(struct {name} *) &base;")?;
-            writeln!(h, "}}")?;
-
-            name
-        }
-        Type::Enum(e) => {
-            let name = type_name(ty, type_map);
-            remap.insert(ty.clone(), name.clone());
+    /// How to let go of what a stack place holds on the heap when it dies.
+    pub fn releases(&self, func: &Function, place: usize) -> Vec<Release> {
+        let ty = &func.cfg.place_tys[place];
+        self.releases_of(func, ty, &[place], &mut HashSet::new())
+    }
 
-            for variant in &e.variants {
-                compile_ty(h, variant, remap, type_map)?;
+    // A value it holds in several places still only has the one reference, so `dropped`
+    // keeps track of those
+    fn releases_of(
+        &self,
+        func: &Function,
+        ty: &Type,
+        nodes: &[usize],
+        dropped: &mut HashSet<usize>,
+    ) -> Vec<Release> {
+        let (deps, aliases) = (func.deps, &func.aliases);
+        let alts: Vec<_> = nodes
+            .iter()
+            .flat_map(|&n| aliases.alternatives(n).iter().copied().collect::<Vec<_>>())
+            .collect();
+        let held = |i: usize| -> Vec<usize> {
+            alts.iter()
+                .filter_map(|&a| deps.nodes[a].deps.get().get(i).copied())
+                .collect()
+        };
+        let on_heap = |held: &[usize]| held.iter().any(|&e| deps.heap_stored(e));
+        let atomic = |held: &[usize]| held.iter().any(|&e| self.atomic(func, e));
+
+        let mut releases = vec![];
+        match self.resolve(ty) {
+            Type::Tuple(t) => {
+                for (elem, ty) in t.elems.iter().enumerate() {
+                    let held = held(elem);
+                    if on_heap(&held) {
+                        let values: HashSet<_> = held
+                            .iter()
+                            .flat_map(|&e| aliases.alternatives(e).iter().copied().collect::<Vec<_>>())
+                            .collect();
+                        if values.is_subset(dropped) {
+                            continue;
+                        }
+                        dropped.extend(values);
+
+                        let atomic = atomic(&held);
+                        releases.push(Release::Drop { elem, ty: ty.clone(), atomic });
+                    } else if !held.is_empty() {
+                        let inner = self.releases_of(func, ty, &held, dropped);
+                        if !inner.is_empty() {
+                            releases.push(Release::Follow { elem, ty: ty.clone(), inner });
+                        }
+                    }
+                }
             }
+            Type::Enum(e) => {
+                let held = held(0);
+                if on_heap(&held) {
+                    releases.push(Release::Payload { atomic: atomic(&held) });
+                } else if !held.is_empty() {
+                    let variants: Vec<_> = e
+                        .variants
+                        .iter()
+                        .enumerate()
+                        .map(|(i, variant)| {
+                            let inner = self.releases_of(func, variant, &held, dropped);
+                            (i, variant.clone(), inner)
+                        })
+                        .filter(|(_, _, inner)| !inner.is_empty())
+                        .collect();
+                    if !variants.is_empty() {
+                        releases.push(Release::Variants(variants));
+                    }
+                }
+            }
+            Type::Named(_) => unreachable!(),
+        }
 
-            // Create the enum struct
-            writeln!(h, "// {ty:?}")?;
-            writeln!(h, "struct {name} {{")?;
-            writeln!(h, "int disc;")?;
-            writeln!(h, "union {{")?;
+        releases
+    }
 
-            for (i, variant) in e.variants.iter().enumerate() {
-                writeln!(h, "struct {} v{i};", type_name(variant, type_map))?;
-            }
+    pub fn resolve<'t>(&'t self, ty: &'t Type) -> &'t Type {
+        resolve(ty, self.type_map)
+    }
 
-            writeln!(h, "}} *inner;")?;
-            writeln!(h, "}};\n")?;
+    /// The variant of the enum `ty` a constructor builds out of a `payload`, which is the
+    /// first one with the payload's type.
+    pub fn variant_of(&self, ty: &Type, payload: &Type) -> usize {
+        let Type::Enum(e) = self.resolve(ty) else {
+            panic!("{ty:?} isn't an enum");
+        };
 
-            name
-        }
-        Type::Named(n) => {
-            let aliased_ty = type_map.get(n).unwrap();
-            return compile_ty(h, aliased_ty, remap, type_map);
-        }
-    };
+        e.variants
+            .iter()
+            .position(|v| same_type(v, payload, self.type_map, &mut HashSet::new()))
+            .unwrap_or_else(|| panic!("{payload:?} isn't a variant of {ty:?}"))
+    }
 
-    // Create the allocation function for the type
-    writeln!(h, "struct {name} *allocate_{name}(struct {name} val) {{")?;
-    writeln!(h, "struct {name} *a = malloc(sizeof(val));")?;
-    writeln!(h, "*a = val;")?;
-    writeln!(h, "return a;")?;
-    writeln!(h, "}}")?;
+    pub fn type_name(&self, ty: &Type) -> String {
+        self.names.get(ty, self.type_map)
+    }
+}
 
-    Ok(())
+/// A step in letting go of what a stack value holds on the heap. Its elements are
+/// pointers, which either are references to drop or point at other stack values.
+#[derive(Debug)]
+pub enum Release {
+    /// Drops the reference in an element
+    Drop { elem: usize, ty: Type, atomic: bool },
+    /// Lets go of what the stack value an element points at holds
+    Follow {
+        elem: usize,
+        ty: Type,
+        inner: Vec<Release>,
+    },
+    /// Drops the payload of an enum, whichever variant it is
+    Payload { atomic: bool },
+    /// Lets go of what the payload of an enum holds, for each variant that holds anything
+    Variants(Vec<(usize, Type, Vec<Release>)>),
 }
 
-fn type_name(ty: &Type, type_map: &HashMap<String, Type>) -> String {
-    use convert_base::Convert;
+/// A code generator. The driver hands it the module one piece at a time: the prologue,
+/// the types used, then the signature, blocks, statements and terminators of every
+/// function, and finally the epilogue.
+pub trait Backend {
+    fn prologue(&mut self, module: &Module) -> io::Result<()>;
+
+    /// Called once with every type any place has, before any function is compiled.
+    fn declare_types(&mut self, module: &Module, tys: &[&Type]) -> io::Result<()>;
 
-    const ID_CHARS: &[u8] =
-        "QWERTYUIOPASDFGHJKLZXCVBNMqwertyuiopasdfghjklzxcvbnm1234567890_".as_bytes();
+    fn signature(&mut self, module: &Module, func: &Function) -> io::Result<()>;
 
-    if let Type::Named(n) = ty {
-        return type_name(type_map.get(n).unwrap(), type_map);
+    /// Emits the body of a function. By default every reachable block is visited in
+    /// depth first order, starting from the entry block.
+    fn body(&mut self, module: &Module, func: &Function) -> io::Result<()> {
+        walk_blocks(self, module, func)
     }
 
-    let mut h = DefaultHasher::new();
-    ty.hash(&mut h);
+    /// Starts a block, before any of its statements.
+    fn block(&mut self, module: &Module, func: &Function, block: usize) -> io::Result<()>;
+
+    fn statement(
+        &mut self,
+        module: &Module,
+        func: &Function,
+        point: (usize, usize),
+        stmnt: &Statement,
+    ) -> io::Result<()>;
 
-    let encoded = Convert::new(64, ID_CHARS.len() as u64).convert::<u64, u8>(&[h.finish()]);
-    let encoded_str: String =
-        String::from_utf8(encoded.into_iter().map(|i| ID_CHARS[i as usize]).collect()).unwrap();
+    fn terminator(
+        &mut self,
+        module: &Module,
+        func: &Function,
+        block: usize,
+        term: &Terminator,
+    ) -> io::Result<()>;
 
-    format!("ty_{encoded_str}")
+    fn end_function(&mut self, module: &Module, func: &Function) -> io::Result<()>;
+
+    fn epilogue(&mut self, module: &Module) -> io::Result<()>;
 }
 
-pub fn compile_cfg(
-    mut c: impl io::Write,
-    mut h: impl io::Write,
-    cfg: &Cfg,
-    deps: &DepGraph,
-    other_fns: &HashMap<Ident, (Cfg, DepGraph)>,
-    type_map: &HashMap<String, Type>,
+/// Hands every block reachable from the entry block to a backend, in depth first order.
+pub fn walk_blocks<B: Backend + ?Sized>(
+    backend: &mut B,
+    module: &Module,
+    func: &Function,
 ) -> io::Result<()> {
-    let ret_ty = type_name(&cfg.place_tys[0], type_map);
-    let alloced = if deps.nodes[0].allocated() { "*" } else { "" };
-    write!(c, "struct {ret_ty} {alloced}P_{}(", cfg.name)?;
-    write!(h, "struct {ret_ty} {alloced}P_{}(", cfg.name)?;
-
-    for arg in 1..=cfg.arg_count {
-        let arg_ty = type_name(&cfg.place_tys[arg], type_map);
-        write!(c, "struct {arg_ty} *r{arg}")?;
-        write!(h, "struct {arg_ty} *r{arg}")?;
-
-        if arg != cfg.arg_count {
-            write!(c, ", ")?;
-            write!(h, ", ")?;
+    let cfg = func.cfg;
+    let mut visited = vec![false; cfg.basic_blocks.len()];
+    let mut bb_stack = vec![0];
+    while let Some(bb) = bb_stack.pop() {
+        if visited[bb] {
+            continue;
         }
+        visited[bb] = true;
+
+        let block = &cfg.basic_blocks[bb];
+        backend.block(module, func, bb)?;
+        for (i, stmnt) in block.stmnts.iter().enumerate() {
+            backend.statement(module, func, (bb, i), stmnt)?;
+        }
+        backend.terminator(module, func, bb, block.terminator.as_ref().unwrap())?;
+
+        bb_stack.extend(cfg.successors(bb).into_iter().filter(|s| !visited[*s]));
     }
 
-    // TODO: currently using non_ret_new_lives is a little incorrect
-    // we need to handle the case where ther returned value is Xor(allocated thing, unallocated thing)
-    let arged_new = deps.non_ret_new_lives();
-    for new in &arged_new {
-        write!(c, ", ")?;
-        write!(h, ", ")?;
+    Ok(())
+}
 
-        let arg_ty = type_name(&cfg.place_tys[*new], type_map);
-        write!(c, "struct {arg_ty} *r{new}")?;
-        write!(h, "struct {arg_ty} *r{new}")?;
+/// Picks a backend by name, writing its output to `dir`.
+pub fn create_backend(name: &str, dir: impl AsRef<Path>) -> io::Result<Box<dyn Backend>> {
+    match name {
+        "c" => Ok(Box::new(c::CBackend::create(dir)?)),
+        "llvm" => Ok(Box::new(llvm::LlvmBackend::create(dir)?)),
+        _ => Err(invalid_input(format!(
+            "unknown backend `{name}`, expected `c` or `llvm`"
+        ))),
     }
+}
 
-    writeln!(c, ") {{")?;
-    writeln!(h, ");")?;
+pub fn compile_module(
+    backend: &mut dyn Backend,
+    fns: &HashMap<Ident, (Cfg, DepGraph)>,
+    type_map: &HashMap<String, Type>,
+    opts: Options,
+) -> io::Result<()> {
+    // Functions are compiled in name order so that the output is the same every run
+    let mut names: Vec<_> = fns.keys().collect();
+    names.sort_by(|a, b| a.0.cmp(&b.0));
 
-    for p in (cfg.arg_count + 1)..cfg.place_tys.len() {
-        if arged_new.contains(&p) {
-            continue;
+    let mut used_types = vec![];
+    for name in &names {
+        used_types.extend(&fns[*name].0.place_tys);
+    }
+
+    if let Some(entry) = &opts.entry {
+        match fns.get(entry) {
+            Some((cfg, _)) if cfg.arg_count == 0 => {}
+            Some(_) => return Err(invalid_input(format!("`{entry}` can't take arguments"))),
+            None => return Err(invalid_input(format!("there's no function `{entry}`"))),
         }
+    }
 
-        let ptr = if deps.nodes[p].allocated()
-            || matches!(&deps.nodes[p].deps, Deps::Xor(ds) if ds.iter().any(|d| deps.nodes[*d].allocated()))
-        {
-            "*"
-        } else {
-            ""
-        };
+    let boundaries = opts.thread_boundaries.iter().cloned().collect();
+    let shared = threads::thread_shared(fns, &boundaries);
+
+    let unit = Type::unit();
+    let module = Module {
+        fns,
+        type_map,
+        opts,
+        names: TypeNames::new(
+            type_map,
+            |n| !fns.contains_key(&n.into()),
+            used_types.iter().copied().chain([&unit]),
+        ),
+        shared,
+    };
+
+    backend.prologue(&module)?;
+    backend.declare_types(&module, &used_types)?;
 
-        writeln!(
-            c,
-            "struct {} {ptr}r{p};",
-            type_name(&cfg.place_tys[p], type_map)
-        )?;
+    for name in names {
+        let (cfg, deps) = &fns[name];
+        let func = Function::new(cfg, deps);
+        backend.signature(&module, &func)?;
+        backend.body(&module, &func)?;
+        backend.end_function(&module, &func)?;
     }
 
-    let mut visited = vec![false; cfg.basic_blocks.len()];
-    let mut bb_stack = vec![0];
-    while let Some(bb) = bb_stack.pop() {
-        visited[bb] = true;
+    backend.epilogue(&module)
+}
 
-        writeln!(c, "L_{bb}:")?;
+// New lives that aren't returned are written into buffers the caller provides, unless
+// they're allocated and so don't need any storage from the caller
+fn arged_new(deps: &DepGraph) -> Vec<usize> {
+    let mut buffers: Vec<_> = deps
+        .non_ret_new_lives()
+        .into_iter()
+        .filter(|&n| !deps.nodes[n].allocated())
+        .collect();
+    buffers.sort();
+    buffers
+}
 
-        let block = &cfg.basic_blocks[bb];
-        for stmnt in &block.stmnts {
-            match stmnt {
-                Statement::Assign(a) => {
-                    let new_buffers = match &a.value {
-                        Value::Call { func, .. } => match other_fns.get(func) {
-                            None => vec![],
-                            Some((f_cfg, f_deps)) => {
-                                let f_arged_new = f_deps.non_ret_new_lives();
-                                f_deps
-                                    .new_lives
-                                    .iter()
-                                    .filter(|new| f_arged_new.contains(*new))
-                                    .map(|new| type_name(&f_cfg.place_tys[*new], type_map))
-                                    .enumerate()
-                                    .collect()
-                            }
-                        },
-                        _ => vec![],
-                    };
-
-                    for (i, new_ty) in &new_buffers {
-                        writeln!(c, "struct {new_ty} r{}n{i};", a.place)?;
-                    }
+fn invalid_input(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
 
-                    let c_name = type_name(&cfg.place_tys[a.place], type_map);
+fn resolve<'t>(mut ty: &'t Type, type_map: &'t HashMap<String, Type>) -> &'t Type {
+    while let Type::Named(n) = ty {
+        ty = &type_map[n];
+    }
+    ty
+}
 
-                    if arged_new.contains(&a.place) {
-                        write!(c, "*")?;
-                    }
-                    write!(c, "r{} = ", a.place)?;
+// Whether two types have the same structure once their names are looked through. Names
+// that are already being compared are assumed to match, so recursive types terminate.
+fn same_type(
+    a: &Type,
+    b: &Type,
+    type_map: &HashMap<String, Type>,
+    assumed: &mut HashSet<(Type, Type)>,
+) -> bool {
+    if a == b {
+        return true;
+    }
+    if matches!(a, Type::Named(_)) || matches!(b, Type::Named(_)) {
+        if !assumed.insert((a.clone(), b.clone())) {
+            return true;
+        }
+        return same_type(
+            resolve(a, type_map),
+            resolve(b, type_map),
+            type_map,
+            assumed,
+        );
+    }
 
-                    let mut closing_parens = 0;
-                    if a.allocate {
-                        write!(c, "allocate_{c_name}(")?;
-                        closing_parens += 1;
-                    }
+    match (a, b) {
+        (Type::Tuple(_), Type::Tuple(_)) | (Type::Enum(_), Type::Enum(_)) => {
+            let (a, b) = (parts(a), parts(b));
+            a.len() == b.len()
+                && a.iter()
+                    .zip(b)
+                    .all(|(a, b)| same_type(a, b, type_map, assumed))
+        }
+        _ => false,
+    }
+}
 
-                    match &a.value {
-                        Value::Place(p) => write!(c, "r{p}")?,
-                        Value::Call { func, .. } if func.0.as_str() == "invent" => {
-                            write!(c, "invent()")?;
-                        }
-                        Value::Call { func, args } => {
-                            match func.0.as_str() {
-                                "tuple" => {
-                                    closing_parens += 1;
-                                    write!(c, "from_tuple_base{c_name}({func}{}(", args.len())?
-                                }
-                                name => write!(c, "P_{name}(")?,
-                            };
-
-                            for (i, arg) in args.iter().enumerate() {
-                                if !deps.nodes[*arg].allocated()
-                                    && !(1..=cfg.arg_count).contains(arg)
-                                    && !arged_new.contains(arg)
-                                {
-                                    write!(c, "&")?;
-                                }
-                                write!(c, "r{arg}")?;
-
-                                if i + 1 != args.len() {
-                                    write!(c, ", ")?;
-                                }
-                            }
-
-                            for (i, _) in new_buffers {
-                                write!(c, ", &r{}n{i}", a.place)?;
-                            }
-
-                            write!(c, ")")?;
-                        }
-                    }
+// The elements of a tuple or the variants of an enum
+fn parts(ty: &Type) -> &[Type] {
+    match ty {
+        Type::Tuple(t) => &t.elems,
+        Type::Enum(e) => &e.variants,
+        Type::Named(_) => &[],
+    }
+}
 
-                    write!(c, "{}", ")".repeat(closing_parens))?;
-                    writeln!(c, ";")?;
-                }
-                Statement::Deallocate(r) => writeln!(c, "deallocate(r{r});")?,
-                Statement::Dup(r) => writeln!(c, "dup(r{}, {});", r.place, r.count)?,
-                Statement::Drop(r) => writeln!(c, "drop(r{}, {});", r.place, r.count)?,
-                Statement::Nop => {}
+// Structs the runtime headers define
+const RESERVED_NAMES: &[&str] = &["region", "region_chunk", "rc_header", "rc_site"];
+
+/// Names every type after the alias it was defined with, or else after its structure, like
+/// `tuple2_unit_List`. Types with the same structure share a name, however much of it is
+/// spelled out with aliases. Names only depend on the types being named and the order
+/// they're given in, so the same module always gets the same names.
+struct TypeNames {
+    names: HashMap<Type, String>,
+    taken: HashSet<String>,
+}
+
+impl TypeNames {
+    fn new<'a>(
+        type_map: &HashMap<String, Type>,
+        is_alias: impl Fn(&str) -> bool,
+        tys: impl IntoIterator<Item = &'a Type>,
+    ) -> Self {
+        let mut out = Self {
+            names: HashMap::new(),
+            taken: RESERVED_NAMES.iter().map(|n| n.to_string()).collect(),
+        };
+
+        // Aliases are named first so that they get to keep their names
+        let mut aliases: Vec<_> = type_map
+            .iter()
+            .filter(|(name, ty)| is_alias(name) && !matches!(ty, Type::Named(_)))
+            .collect();
+        aliases.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, ty) in &aliases {
+            if !out.names.contains_key(*ty) {
+                out.claim(ty, name);
             }
         }
-
-        let succs = cfg.successors(bb);
-        for &s in &succs {
-            let succ = &cfg.basic_blocks[s];
-            for phi in &succ.phi {
-                if let Some(desired_place) = phi.opts.get(&bb) {
-                    // todo: handle the desired place not being a ref already
-                    writeln!(c, "r{} = r{desired_place};", phi.place)?;
-                }
+        for (_, ty) in aliases {
+            for part in parts(ty) {
+                out.name(part, type_map);
             }
         }
 
-        match block.terminator.as_ref().unwrap() {
-            Terminator::Goto(next) => writeln!(c, "goto L_{next};")?,
-            Terminator::Return(r) => writeln!(c, "return r{r};")?,
-            Terminator::IfElse { cond, iff, elsee } => {
-                writeln!(c, "if (r{cond}) goto L_{iff};")?;
-                writeln!(c, "goto L_{elsee};")?;
-            }
+        for ty in tys {
+            out.name(ty, type_map);
         }
 
-        bb_stack.extend(succs.into_iter().filter(|s| !visited[*s]));
+        out
     }
 
-    writeln!(c, "}}\n")?;
+    fn name(&mut self, ty: &Type, type_map: &HashMap<String, Type>) -> String {
+        let ty = resolve(ty, type_map);
+        if let Some(name) = self.find(ty, type_map) {
+            self.names.insert(ty.clone(), name.clone());
+            return name;
+        }
 
-    Ok(())
-}
+        let kind = match ty {
+            Type::Tuple(t) if t.elems.is_empty() => return self.claim(ty, "unit"),
+            Type::Tuple(_) => "tuple",
+            Type::Enum(_) => "enum",
+            Type::Named(_) => unreachable!(),
+        };
 
-pub fn write_std_lib(mut f: impl io::Write, mut types: impl io::Write) -> io::Result<()> {
-    write!(f, "{STD_BASE}")?;
+        let parts: Vec<_> = parts(ty).iter().map(|p| self.name(p, type_map)).collect();
+        self.claim(ty, &format!("{kind}{}_{}", parts.len(), parts.join("_")))
+    }
 
-    for arg_count in 0..10 {
-        // Create the tuple function for this arg count
-        writeln!(types, "struct tuple_base{arg_count} {{")?;
-        for i in 0..arg_count {
-            writeln!(types, "void *e{i};")?;
+    // Gives a type the first free name starting with `base`
+    fn claim(&mut self, ty: &Type, base: &str) -> String {
+        let mut name = base.to_string();
+        let mut i = 1;
+        while self.taken.contains(&name) {
+            name = format!("{base}_{i}");
+            i += 1;
         }
-        writeln!(types, "}};")?;
 
-        write!(f, "struct tuple_base{arg_count} tuple{arg_count}(")?;
-        for i in 0..arg_count {
-            write!(f, "void *r{i}")?;
+        self.taken.insert(name.clone());
+        self.names.insert(ty.clone(), name.clone());
+        name
+    }
 
-            if i + 1 != arg_count {
-                write!(f, ", ")?;
-            }
+    fn get(&self, ty: &Type, type_map: &HashMap<String, Type>) -> String {
+        let ty = resolve(ty, type_map);
+        match self.find(ty, type_map) {
+            Some(name) => name,
+            None => panic!("type {ty:?} was never named"),
         }
-        writeln!(f, ") {{")?;
+    }
 
-        write!(f, "return (struct tuple_base{arg_count}) {{ ")?;
-        for i in 0..arg_count {
-            write!(f, ".e{i} = r{i},")?;
+    // The name of a type with the same structure, if one was named already
+    fn find(&self, ty: &Type, type_map: &HashMap<String, Type>) -> Option<String> {
+        if let Some(name) = self.names.get(ty) {
+            return Some(name.clone());
         }
-        writeln!(f, "}};")?;
 
-        writeln!(f, "}}")?;
+        self.names
+            .iter()
+            .find(|(named, _)| same_type(named, ty, type_map, &mut HashSet::new()))
+            .map(|(_, name)| name.clone())
     }
+}
 
-    Ok(())
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        cfg::test_util::managed_module,
+        types::{Enum, Tuple},
+    };
+
+    fn tuple(elems: Vec<Type>) -> Type {
+        Type::Tuple(Tuple { elems })
+    }
+
+    #[test]
+    fn test_type_names() {
+        let list = Type::Enum(Enum {
+            variants: vec![
+                Type::unit(),
+                tuple(vec![Type::unit(), Type::Named("List".into())]),
+            ],
+        });
+        let type_map = HashMap::from([
+            ("List".to_string(), list.clone()),
+            ("generate".to_string(), Type::Named("List".into())),
+            ("region".to_string(), tuple(vec![Type::unit()])),
+        ]);
+        let pair = tuple(vec![Type::unit(), Type::Named("List".into())]);
+
+        let names = TypeNames::new(
+            &type_map,
+            |n| n != "generate",
+            [&Type::Named("generate".into())],
+        );
+
+        assert_eq!(
+            names.get(&Type::Named("generate".into()), &type_map),
+            "List"
+        );
+        assert_eq!(names.get(&list, &type_map), "List");
+        // the parts of an alias are named even if nothing else uses them
+        assert_eq!(names.get(&pair, &type_map), "tuple2_unit_List");
+        assert_eq!(names.get(&Type::unit(), &type_map), "unit");
+        // aliases can't take the runtime's names
+        assert_eq!(names.get(&tuple(vec![Type::unit()]), &type_map), "region_1");
+        // spelling out an alias doesn't make a new type
+        assert_eq!(
+            names.get(&tuple(vec![Type::unit(), list.clone()]), &type_map),
+            "tuple2_unit_List"
+        );
+    }
+
+    #[test]
+    fn test_create_backend() {
+        let (fns, type_map) = managed_module(include_str!("../../inputs/gen_list.rs"));
+        let dir = std::env::temp_dir().join("perm-mem-test-create-backend");
+
+        for (name, output) in [("c", "program.c"), ("llvm", "program.ll")] {
+            let _ = std::fs::remove_dir_all(dir.join(name));
+            let mut backend = create_backend(name, dir.join(name)).unwrap();
+            compile_module(&mut *backend, &fns, &type_map, Options::default()).unwrap();
+            assert!(dir.join(name).join(output).exists(), "{name}");
+        }
+
+        let Err(err) = create_backend("wasm", &dir) else {
+            panic!("there's no wasm backend");
+        };
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(
+            err.to_string(),
+            "unknown backend `wasm`, expected `c` or `llvm`"
+        );
+    }
+
+    #[test]
+    fn test_source_lines() {
+        let source = Source::new("f.rs", "fn f() -> () {\n    a\n}".to_string());
+
+        assert_eq!(source.line(0), (1, "fn f() -> () {"));
+        assert_eq!(source.line(19), (2, "    a"));
+        assert_eq!(source.line(21), (3, "}"));
+    }
 }
@@ -0,0 +1,219 @@
+#pragma once
+#include <stdlib.h>
+
+#include "alloc.h"
+
+#ifdef RC_DEBUG
+#include <stdio.h>
+#include <string.h>
+
+#define RC_MAX_SITES 1024
+#define RC_POISON 0xdb
+
+// Allocation counts for one place of one function
+struct rc_site {
+    const char *name;
+    long allocs;
+    long frees;
+};
+
+static struct rc_site rc_sites[RC_MAX_SITES];
+static int rc_site_count;
+static long rc_double_frees;
+static long rc_negative_counts;
+
+static void rc_report(void) {
+    long leaked = 0;
+
+    fprintf(stderr, "rc: %-32s %8s %8s %8s\n", "site", "allocs", "frees", "leaked");
+    for (int i = 0; i < rc_site_count; i++) {
+        struct rc_site *site = &rc_sites[i];
+        leaked += site->allocs - site->frees;
+        fprintf(stderr, "rc: %-32s %8ld %8ld %8ld\n", site->name, site->allocs, site->frees,
+                site->allocs - site->frees);
+    }
+
+    fprintf(stderr, "rc: %ld leaked, %ld double frees, %ld negative counts\n", leaked,
+            rc_double_frees, rc_negative_counts);
+}
+
+static struct rc_site *rc_site_named(const char *name) {
+    for (int i = 0; i < rc_site_count; i++) {
+        if (strcmp(rc_sites[i].name, name) == 0) {
+            return &rc_sites[i];
+        }
+    }
+
+    if (rc_site_count == 0) {
+        atexit(rc_report);
+    }
+    if (rc_site_count == RC_MAX_SITES) {
+        fprintf(stderr, "rc: more than %d allocation sites\n", RC_MAX_SITES);
+        abort();
+    }
+
+    rc_sites[rc_site_count].name = name;
+    return &rc_sites[rc_site_count++];
+}
+
+// Freed cells are poisoned and kept around so later uses of them can be caught
+struct rc_header {
+    long count;
+    struct rc_site *site;
+    size_t size;
+    int freed;
+};
+#else
+// Every reference counted cell is preceded by its header
+struct rc_header {
+    long count;
+};
+#endif
+
+static struct rc_header *rc_header_of(void *ptr) {
+    return (struct rc_header *) ptr - 1;
+}
+
+#ifdef RC_DEBUG
+static void *rc_alloc(const char *site, size_t size) {
+    struct rc_header *header = RT_ALLOC(sizeof(struct rc_header) + size);
+    header->count = 1;
+    header->site = rc_site_named(site);
+    header->size = size;
+    header->freed = 0;
+    header->site->allocs++;
+    return header + 1;
+}
+
+static int rc_check_live(struct rc_header *header, const char *op) {
+    if (header->freed) {
+        rc_double_frees++;
+        fprintf(stderr, "rc: %s of a freed cell from %s\n", op, header->site->name);
+        return 0;
+    }
+
+    return 1;
+}
+#else
+static void *rc_alloc(size_t size) {
+    struct rc_header *header = RT_ALLOC(sizeof(struct rc_header) + size);
+    header->count = 1;
+    return header + 1;
+}
+
+#define RC_POOL_SLAB 64
+#define RC_POOL_CELL(size) ((sizeof(struct rc_header) + (size) + 7) & ~(size_t) 7)
+
+// Cells of a single size, which are kept on a free list once they're freed instead of
+// being given back
+struct rc_pool {
+    size_t cell;
+    void *free;
+};
+
+static void *rc_pool_alloc(struct rc_pool *pool) {
+    if (!pool->free) {
+        char *slab = RT_ALLOC(pool->cell * RC_POOL_SLAB);
+        for (int i = 0; i < RC_POOL_SLAB; i++) {
+            void **cell = (void **) (slab + i * pool->cell);
+            *cell = pool->free;
+            pool->free = cell;
+        }
+    }
+
+    void **cell = pool->free;
+    pool->free = *cell;
+
+    struct rc_header *header = (struct rc_header *) cell;
+    header->count = 1;
+    return header + 1;
+}
+
+static void rc_pool_free(struct rc_pool *pool, void *ptr) {
+    void **cell = (void **) rc_header_of(ptr);
+    *cell = pool->free;
+    pool->free = cell;
+}
+#endif
+
+static void rc_dup(void *ptr, int count) {
+    struct rc_header *header = rc_header_of(ptr);
+#ifdef RC_DEBUG
+    if (!rc_check_live(header, "dup")) {
+        return;
+    }
+#endif
+    header->count += count;
+}
+
+// Returns whether the last reference was dropped, in which case the cell should be freed
+static int rc_drop(void *ptr, int count) {
+    struct rc_header *header = rc_header_of(ptr);
+#ifdef RC_DEBUG
+    if (!rc_check_live(header, "drop")) {
+        return 0;
+    }
+#endif
+    header->count -= count;
+#ifdef RC_DEBUG
+    if (header->count < 0) {
+        rc_negative_counts++;
+        fprintf(stderr, "rc: negative count %ld for a cell from %s\n", header->count,
+                header->site->name);
+    }
+#endif
+    return header->count <= 0;
+}
+
+static void rc_free(void *ptr, size_t size) {
+#ifdef RC_DEBUG
+    (void) size;
+    struct rc_header *header = rc_header_of(ptr);
+    if (!rc_check_live(header, "free")) {
+        return;
+    }
+
+    header->freed = 1;
+    header->site->frees++;
+    memset(ptr, RC_POISON, header->size);
+#else
+    RT_FREE(rc_header_of(ptr), sizeof(struct rc_header) + size);
+#endif
+}
+
+#ifdef RC_ATOMIC
+#include <stdatomic.h>
+
+// The same as `rc_dup` and `rc_drop`, for cells another thread may hold. Counts are only
+// ever touched atomically once a cell is shared, so they're updated in place.
+static void rc_dup_atomic(void *ptr, int count) {
+    struct rc_header *header = rc_header_of(ptr);
+#ifdef RC_DEBUG
+    if (!rc_check_live(header, "dup")) {
+        return;
+    }
+#endif
+    atomic_fetch_add_explicit((_Atomic long *) &header->count, count, memory_order_relaxed);
+}
+
+// The last drop has to see every write other threads made before theirs
+static int rc_drop_atomic(void *ptr, int count) {
+    struct rc_header *header = rc_header_of(ptr);
+#ifdef RC_DEBUG
+    if (!rc_check_live(header, "drop")) {
+        return 0;
+    }
+#endif
+    long left = atomic_fetch_sub_explicit((_Atomic long *) &header->count, count,
+                                          memory_order_acq_rel) -
+                count;
+#ifdef RC_DEBUG
+    if (left < 0) {
+        rc_negative_counts++;
+        fprintf(stderr, "rc: negative count %ld for a cell from %s\n", left,
+                header->site->name);
+    }
+#endif
+    return left <= 0;
+}
+#endif
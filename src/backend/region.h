@@ -0,0 +1,43 @@
+#pragma once
+#include <stdlib.h>
+
+#include "alloc.h"
+
+#define REGION_CHUNK_SIZE 4096
+
+struct region_chunk {
+    struct region_chunk *next;
+    size_t used;
+    size_t cap;
+    char data[];
+};
+
+struct region {
+    struct region_chunk *chunks;
+};
+
+static void *region_alloc(struct region *rgn, size_t size) {
+    size = (size + 7) & ~(size_t) 7;
+
+    struct region_chunk *chunk = rgn->chunks;
+    if (!chunk || chunk->cap - chunk->used < size) {
+        size_t cap = size > REGION_CHUNK_SIZE ? size : REGION_CHUNK_SIZE;
+        chunk = RT_ALLOC(sizeof(struct region_chunk) + cap);
+        chunk->next = rgn->chunks;
+        chunk->used = 0;
+        chunk->cap = cap;
+        rgn->chunks = chunk;
+    }
+
+    void *ptr = chunk->data + chunk->used;
+    chunk->used += size;
+    return ptr;
+}
+
+static void region_free(struct region *rgn) {
+    while (rgn->chunks) {
+        struct region_chunk *next = rgn->chunks->next;
+        RT_FREE(rgn->chunks, sizeof(struct region_chunk) + rgn->chunks->cap);
+        rgn->chunks = next;
+    }
+}
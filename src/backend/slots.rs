@@ -0,0 +1,122 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::cfg::{
+    analysis::{deps::DepGraph, lva::LVA},
+    Cfg, Statement,
+};
+
+/// Assigns every local to a C variable, letting locals with the same declaration share one
+/// whenever they are never alive at the same time. A place stays alive for as long as
+/// anything that points to it does, since stack values are referenced by address.
+/// Returns the place whose variable each local is stored in.
+pub fn color_slots(
+    cfg: &Cfg,
+    deps: &DepGraph,
+    decls: &HashMap<usize, String>,
+) -> HashMap<usize, usize> {
+    let interference = interference(cfg, deps);
+
+    let mut locals: Vec<_> = decls.keys().copied().collect();
+    locals.sort();
+
+    let mut slots: Vec<Vec<usize>> = vec![];
+    let mut assignment = HashMap::new();
+    for p in locals {
+        let conflicts = interference.get(&p);
+        let slot = slots.iter_mut().find(|members| {
+            decls[&members[0]] == decls[&p]
+                && !members
+                    .iter()
+                    .any(|m| conflicts.is_some_and(|c| c.contains(m)))
+        });
+
+        match slot {
+            Some(members) => {
+                assignment.insert(p, members[0]);
+                members.push(p);
+            }
+            None => {
+                assignment.insert(p, p);
+                slots.push(vec![p]);
+            }
+        }
+    }
+
+    assignment
+}
+
+fn interference(cfg: &Cfg, deps: &DepGraph) -> HashMap<usize, HashSet<usize>> {
+    let plva = LVA::analyze(cfg).point_lva(cfg);
+    let held = |live: &HashSet<usize>| -> HashSet<usize> {
+        let ctrs = deps.live_ref_counts(live.iter().copied());
+        (0..cfg.place_tys.len()).filter(|&p| ctrs[p] != 0).collect()
+    };
+
+    let mut groups: Vec<HashSet<usize>> = plva.values().map(held).collect();
+
+    // a definition clobbers its variable even if the value is never read
+    let preds = cfg.predecessors();
+    for (b, block) in cfg.basic_blocks.iter().enumerate() {
+        for (j, stmnt) in block.stmnts.iter().enumerate() {
+            if let Statement::Assign(a) = stmnt {
+                let mut group = held(&plva[&(b, j as isize + 1)]);
+                group.insert(a.place);
+                groups.push(group);
+            }
+        }
+
+        // phis are assigned at the end of each predecessor
+        if !block.phi.is_empty() {
+            for &pred in preds.get(&b).into_iter().flatten() {
+                let pred_end = (pred, cfg.basic_blocks[pred].stmnts.len() as isize);
+                let mut group = held(&plva[&pred_end]);
+                group.extend(block.phi.iter().map(|phi| phi.place));
+                groups.push(group);
+            }
+        }
+    }
+
+    let mut interference: HashMap<usize, HashSet<usize>> = HashMap::new();
+    for group in groups {
+        for &p in &group {
+            interference.entry(p).or_default().extend(&group);
+        }
+    }
+
+    interference
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        cfg::analysis::{deps::DepGraph, Context},
+        parser,
+    };
+
+    #[test]
+    fn test_slots_share_dead_places() {
+        let module = parser::ModuleParser::new()
+            .parse(
+                "fn f(a: ()) -> (((),), ()) {
+                    let b = invent(a);
+                    let c = invent(b);
+                    let t = tuple(c);
+                    let e = invent(a);
+                    tuple(t, e)
+                }",
+            )
+            .unwrap();
+        let cfg = Cfg::from_ast(module.fns[0].clone(), HashMap::new());
+        let deps = DepGraph::from_cfg(&mut Context::new(), &cfg, false);
+
+        let decls = (2..cfg.place_tys.len())
+            .map(|p| (p, format!("struct {:?} ", cfg.place_tys[p])))
+            .collect();
+        let slots = color_slots(&cfg, &deps, &decls);
+
+        // b dies as c is made, but c lives on for as long as the tuple pointing at it
+        assert_eq!(slots[&3], 2);
+        assert_eq!(slots[&5], 5);
+    }
+}
@@ -3,12 +3,3 @@
 unit invent() {
     return (unit) {};
 }
-
-void dup(void *ptr, int count) {
-}
-
-void drop(void *ptr, int count) {
-}
-
-
-
@@ -0,0 +1,163 @@
+use std::collections::HashSet;
+
+use crate::cfg::{Cfg, Terminator};
+
+/// A piece of a function's body laid out as structured code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Shape {
+    /// The statements of a block
+    Block(usize),
+    /// Moving from one block to another, which assigns the phis of the target
+    Edge(usize, usize),
+    /// Branching on the condition ending a block. Both sides continue at the block's
+    /// immediate post dominator, if it has one.
+    If {
+        block: usize,
+        then: Vec<Shape>,
+        elsee: Vec<Shape>,
+    },
+    /// Returning from the end of a block
+    Return(usize),
+}
+
+/// Lays out a function as nested ifs, where every branch rejoins at its immediate post
+/// dominator. Returns `None` if that would need a block to be written more than once,
+/// which is the case for loops and for branches that don't nest.
+pub fn structure(cfg: &Cfg) -> Option<Vec<Shape>> {
+    let ipdoms = immediate_post_dominators(cfg);
+    let mut seen = vec![false; cfg.basic_blocks.len()];
+    region(cfg, &ipdoms, 0, None, &mut seen)
+}
+
+// Everything from `start` up until `stop`
+fn region(
+    cfg: &Cfg,
+    ipdoms: &[Option<usize>],
+    start: usize,
+    stop: Option<usize>,
+    seen: &mut [bool],
+) -> Option<Vec<Shape>> {
+    let mut out = vec![];
+    let mut b = start;
+    while Some(b) != stop {
+        if seen[b] {
+            return None;
+        }
+        seen[b] = true;
+
+        out.push(Shape::Block(b));
+        match cfg.basic_blocks[b].terminator.as_ref()? {
+            Terminator::Goto(next) => {
+                out.push(Shape::Edge(b, *next));
+                b = *next;
+            }
+            Terminator::Return(_) => {
+                out.push(Shape::Return(b));
+                break;
+            }
+            Terminator::IfElse { iff, elsee, .. } => {
+                let join = ipdoms[b];
+                let mut arm = |s: usize| {
+                    let mut shapes = vec![Shape::Edge(b, s)];
+                    shapes.extend(region(cfg, ipdoms, s, join, seen)?);
+                    Some(shapes)
+                };
+
+                out.push(Shape::If {
+                    block: b,
+                    then: arm(*iff)?,
+                    elsee: arm(*elsee)?,
+                });
+
+                match join {
+                    Some(join) => b = join,
+                    None => break,
+                }
+            }
+        }
+    }
+
+    Some(out)
+}
+
+// Blocks that every path from a block to a return goes through, found by iterating to a
+// fixed point. Blocks that can't return are post dominated by everything.
+fn immediate_post_dominators(cfg: &Cfg) -> Vec<Option<usize>> {
+    let count = cfg.basic_blocks.len();
+    let all: HashSet<_> = (0..count).collect();
+    let mut pdoms = vec![all; count];
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for b in (0..count).rev() {
+            let mut new = cfg
+                .successors(b)
+                .into_iter()
+                .map(|s| pdoms[s].clone())
+                .reduce(|a, b| a.intersection(&b).copied().collect())
+                .unwrap_or_default();
+            new.insert(b);
+
+            if new != pdoms[b] {
+                pdoms[b] = new;
+                changed = true;
+            }
+        }
+    }
+
+    // Post dominators form a chain, so the nearest one is post dominated by all the others
+    (0..count)
+        .map(|b| {
+            pdoms[b]
+                .iter()
+                .copied()
+                .filter(|&d| d != b)
+                .max_by_key(|&d| pdoms[d].len())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser;
+
+    fn cfg(src: &str) -> Cfg {
+        let module = parser::ModuleParser::new().parse(src).unwrap();
+        Cfg::from_ast(module.fns[0].clone(), Default::default())
+    }
+
+    #[test]
+    fn test_nested_diamonds() {
+        let cfg = cfg("fn f(a: ()) -> () {
+                if a {
+                    if a { a } else { a }
+                } else {
+                    a
+                }
+            }");
+
+        let shapes = structure(&cfg).unwrap();
+        let [Shape::Block(0), Shape::If { then, elsee, .. }, Shape::Block(join), ..] = &shapes[..]
+        else {
+            panic!("{shapes:?}");
+        };
+
+        assert!(matches!(
+            then[..],
+            [Shape::Edge(0, 1), Shape::Block(1), Shape::If { .. }, ..]
+        ));
+        assert!(
+            matches!(elsee[..], [Shape::Edge(0, 2), Shape::Block(2), Shape::Edge(2, j)] if j == *join)
+        );
+    }
+
+    #[test]
+    fn test_loops_are_not_structured() {
+        let mut cfg = cfg("fn f(a: ()) -> () { if a { a } else { a } }");
+        cfg.basic_blocks[1].terminator = Some(Terminator::Goto(0));
+
+        assert_eq!(structure(&cfg), None);
+    }
+}
@@ -0,0 +1,237 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use super::deps::{DepGraph, Deps};
+
+/// Alias and points-to queries over a `DepGraph`, caching what they have already
+/// walked. Build one per graph and share it, since the caches live as long as it does.
+pub struct Aliases<'a> {
+    deps: &'a DepGraph,
+    points_to: RefCell<HashMap<usize, Rc<HashSet<usize>>>>,
+    alternatives: RefCell<HashMap<usize, Rc<HashSet<usize>>>>,
+    contains: RefCell<HashMap<(usize, usize), bool>>,
+}
+
+impl DepGraph {
+    pub fn aliases(&self) -> Aliases<'_> {
+        Aliases {
+            deps: self,
+            points_to: RefCell::default(),
+            alternatives: RefCell::default(),
+            contains: RefCell::default(),
+        }
+    }
+}
+
+impl<'a> Aliases<'a> {
+    /// Every place reachable from `place` through its deps, not including itself unless
+    /// it's part of a cycle.
+    pub fn points_to(&self, place: usize) -> Rc<HashSet<usize>> {
+        if let Some(cached) = self.points_to.borrow().get(&place) {
+            return cached.clone();
+        }
+
+        let found = Rc::new(self.walk(place, |_| true));
+        self.points_to.borrow_mut().insert(place, found.clone());
+        found
+    }
+
+    /// The places that aren't aliases themselves which `place` could be the value of.
+    pub fn alternatives(&self, place: usize) -> Rc<HashSet<usize>> {
+        if let Some(cached) = self.alternatives.borrow().get(&place) {
+            return cached.clone();
+        }
+
+        let found = match &self.deps.nodes[place].deps {
+            Deps::All(_) => HashSet::from_iter([place]),
+            Deps::Xor(_) => self
+                .walk(place, |n| matches!(self.deps.nodes[n].deps, Deps::Xor(_)))
+                .into_iter()
+                .filter(|&n| matches!(self.deps.nodes[n].deps, Deps::All(_)))
+                .collect(),
+        };
+
+        let found = Rc::new(found);
+        self.alternatives.borrow_mut().insert(place, found.clone());
+        found
+    }
+
+    /// Whether `a` and `b` could hold the same value. Anything the caller handed over could
+    /// be the same value twice, since nothing stops it from passing one value as several
+    /// arguments.
+    pub fn may_alias(&self, a: usize, b: usize, arg_count: usize) -> bool {
+        if a == b {
+            return true;
+        }
+
+        let (a, b) = (self.alternatives(a), self.alternatives(b));
+        if !a.is_disjoint(&b) {
+            return true;
+        }
+
+        let caller_values = self.caller_values(arg_count);
+        a.iter().any(|n| caller_values.contains(n)) && b.iter().any(|n| caller_values.contains(n))
+    }
+
+    /// Whether every value `a` could be holds `b` somewhere inside of it.
+    pub fn must_contain(&self, a: usize, b: usize) -> bool {
+        if let Some(&cached) = self.contains.borrow().get(&(a, b)) {
+            return cached;
+        }
+
+        let found = self.contains_from(a, b, &mut HashSet::new());
+        self.contains.borrow_mut().insert((a, b), found);
+        found
+    }
+
+    /// The returned value and everything it could point to.
+    pub fn reachable_from_return(&self) -> HashSet<usize> {
+        let mut reachable = (*self.points_to(0)).clone();
+        reachable.insert(0);
+        reachable
+    }
+
+    /// The arguments whose values can flow into the returned value.
+    pub fn args_in_result(&self, arg_count: usize) -> HashSet<usize> {
+        let reachable = self.reachable_from_return();
+        (1..=arg_count).filter(|a| reachable.contains(a)).collect()
+    }
+
+    /// Everything reachable from the return value or the caller's buffers, which has to
+    /// outlive this call.
+    pub fn escaping(&self) -> HashSet<usize> {
+        let mut escaping = self.reachable_from_return();
+        for &new in &self.deps.new_lives {
+            escaping.insert(new);
+            escaping.extend(self.points_to(new).iter());
+        }
+
+        escaping
+    }
+
+    // The arguments and everything inside of them
+    fn caller_values(&self, arg_count: usize) -> HashSet<usize> {
+        let mut found = HashSet::new();
+        for arg in 1..=arg_count {
+            found.insert(arg);
+            found.extend(self.points_to(arg).iter());
+        }
+
+        found
+    }
+
+    // A value on a cycle was built from one made by going around it fewer times, so the
+    // nodes already being looked at are assumed to hold `b` and the alternatives that start
+    // the cycle decide. That only holds while they are, so only the answer for the node
+    // asked about is cached.
+    fn contains_from(&self, a: usize, b: usize, visiting: &mut HashSet<usize>) -> bool {
+        if !visiting.insert(a) {
+            return true;
+        }
+
+        let found = match &self.deps.nodes[a].deps {
+            Deps::All(ds) => ds.iter().any(|&d| d == b || self.contains_from(d, b, visiting)),
+            Deps::Xor(alts) => {
+                !alts.is_empty() && alts.iter().all(|&alt| self.contains_from(alt, b, visiting))
+            }
+        };
+
+        visiting.remove(&a);
+        found
+    }
+
+    // Everything reachable from start, only continuing through nodes that pass `follow`
+    fn walk(&self, start: usize, follow: impl Fn(usize) -> bool) -> HashSet<usize> {
+        let mut found = HashSet::new();
+        let mut stack = vec![start];
+
+        while let Some(node) = stack.pop() {
+            if node != start && !follow(node) {
+                continue;
+            }
+
+            for &dep in self.deps.nodes[node].deps.get() {
+                if found.insert(dep) {
+                    stack.push(dep);
+                }
+            }
+        }
+
+        found
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cfg::analysis::deps::{Node, Perm};
+
+    fn graph(deps: Vec<Deps>) -> DepGraph {
+        let mut graph = DepGraph::opaque();
+        graph.nodes = deps
+            .into_iter()
+            .map(|deps| Node {
+                deps,
+                ..Node::leaf(Perm::Clear)
+            })
+            .collect();
+        graph
+    }
+
+    #[test]
+    fn test_alias_queries() {
+        // _0 returns either _3 or _4, which both hold _1, while only _4 holds _2
+        let deps = graph(vec![
+            Deps::Xor(vec![5]),
+            Deps::All(vec![]),
+            Deps::All(vec![]),
+            Deps::All(vec![1]),
+            Deps::All(vec![1, 2]),
+            Deps::Xor(vec![3, 4]),
+        ]);
+        let aliases = deps.aliases();
+
+        assert_eq!(*aliases.alternatives(0), HashSet::from_iter([3, 4]));
+        assert!(aliases.may_alias(0, 4, 2));
+        assert!(!aliases.may_alias(3, 4, 2));
+        // the caller could pass the same value for both arguments
+        assert!(aliases.may_alias(1, 2, 2));
+        assert!(!aliases.may_alias(1, 2, 0));
+        assert_eq!(aliases.args_in_result(2), HashSet::from_iter([1, 2]));
+        assert_eq!(*aliases.points_to(3), HashSet::from_iter([1]));
+
+        // whichever value _0 is, it holds _1, but only one of them holds _2
+        assert!(aliases.must_contain(0, 1));
+        assert!(!aliases.must_contain(0, 2));
+        assert!(aliases.must_contain(4, 2));
+        assert!(!aliases.must_contain(1, 1));
+    }
+
+    #[test]
+    fn test_must_contain_cycles() {
+        // _1 is a loop phi that starts as _2, which holds _3, and is then rebuilt as _4,
+        // which holds the previous _1 and _5
+        let deps = graph(vec![
+            Deps::Xor(vec![1]),
+            Deps::Xor(vec![4, 2]),
+            Deps::All(vec![3]),
+            Deps::All(vec![]),
+            Deps::All(vec![1, 5]),
+            Deps::All(vec![]),
+        ]);
+        let aliases = deps.aliases();
+
+        // every time around the loop still holds _3 from the first _1, which never holds _5
+        assert!(aliases.must_contain(4, 3));
+        assert!(aliases.must_contain(0, 3));
+        assert!(aliases.must_contain(4, 5));
+        assert!(!aliases.must_contain(1, 5));
+
+        // _4 holds _0 while _1 is assumed to, but the first _1 doesn't, so neither does _4
+        assert!(!aliases.must_contain(1, 0));
+        assert!(!aliases.must_contain(4, 0));
+    }
+}
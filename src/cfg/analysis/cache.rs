@@ -0,0 +1,487 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+    fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+use super::{
+    deps::{DepGraph, Deps, Escape, Node, Perm, Share},
+    effects::Effect,
+    Context, Function,
+};
+use crate::{
+    ast::Ident,
+    cfg::{Cfg, Statement, Value},
+    types::Type,
+};
+
+// Bump this whenever the analysis changes so that stale summaries are ignored
+const CACHE_VERSION: u64 = 6;
+
+// FNV-1a, since keys have to stay the same across builds of the compiler, which the
+// standard library's hasher doesn't promise
+struct StableHasher(u64);
+
+impl StableHasher {
+    fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= u64::from(b);
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Context {
+    /// Replaces (or adds) a function, invalidating every summary that depended on it. Heap
+    /// copies of it are replaced too.
+    pub fn update_cfg(&mut self, cfg: Cfg) {
+        self.invalidate(&cfg.name);
+
+        for (copy, _) in self.heap_copies.iter().filter(|(_, f)| **f == cfg.name) {
+            let mut copied = cfg.clone();
+            copied.name = copy.clone();
+            self.fns.insert(
+                copy.clone(),
+                Function {
+                    cfg: copied,
+                    deps: None,
+                },
+            );
+        }
+
+        self.fns
+            .insert(cfg.name.clone(), Function { cfg, deps: None });
+    }
+
+    /// Removes a function along with its heap copies.
+    pub fn remove_fn(&mut self, ident: &Ident) -> Option<Cfg> {
+        self.invalidate(ident);
+
+        let copies: Vec<_> = self
+            .heap_copies
+            .iter()
+            .filter(|(_, f)| *f == ident)
+            .map(|(copy, _)| copy.clone())
+            .collect();
+        for copy in copies {
+            self.heap_copies.remove(&copy);
+            self.fns.remove(&copy);
+        }
+
+        self.fns.remove(ident).map(|f| f.cfg)
+    }
+
+    /// Forgets the summary of a function and of everything that transitively calls it.
+    pub fn invalidate(&mut self, ident: &Ident) {
+        let mut stale = self.dependents(ident);
+        stale.insert(ident.clone());
+
+        for name in stale {
+            if let Some(f) = self.fns.get_mut(&name) {
+                f.deps = None;
+            }
+        }
+    }
+
+    /// The user functions directly called by a function.
+    pub fn callees(&self, ident: &Ident) -> HashSet<Ident> {
+        let Some(cfg) = self.get_cfg(ident) else {
+            return HashSet::new();
+        };
+
+        cfg.statements()
+            .filter_map(|s| match s {
+                Statement::Assign(a) => match &a.value {
+                    Value::Call { func, .. } if self.fns.contains_key(func) => Some(func.clone()),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Every function whose summary transitively depends on the given one, including heap
+    /// copies of any of them.
+    pub fn dependents(&self, ident: &Ident) -> HashSet<Ident> {
+        let mut callers: HashMap<_, Vec<_>> = HashMap::new();
+        for name in self.fns.keys() {
+            for callee in self.callees(name) {
+                callers.entry(callee).or_default().push(name.clone());
+            }
+        }
+        for (copy, original) in &self.heap_copies {
+            callers
+                .entry(original.clone())
+                .or_default()
+                .push(copy.clone());
+        }
+
+        let mut dependents = HashSet::new();
+        let mut stack = vec![ident.clone()];
+        while let Some(name) = stack.pop() {
+            for caller in callers.get(&name).into_iter().flatten() {
+                if dependents.insert(caller.clone()) {
+                    stack.push(caller.clone());
+                }
+            }
+        }
+
+        dependents
+    }
+
+    // A summary only depends on the cfgs of the functions it can reach, and the definitions
+    // of the types they use
+    fn summary_key(&self, ident: &Ident) -> u64 {
+        let mut reachable: HashSet<_> = HashSet::from_iter([ident.clone()]);
+        let mut stack = vec![ident.clone()];
+        while let Some(name) = stack.pop() {
+            for callee in self.callees(&name) {
+                if reachable.insert(callee.clone()) {
+                    stack.push(callee);
+                }
+            }
+        }
+
+        let mut reachable: Vec<_> = reachable.into_iter().collect();
+        reachable.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut h = StableHasher::new();
+        CACHE_VERSION.hash(&mut h);
+        self.alloc_policy.hash(&mut h);
+        let mut named = HashSet::new();
+        for name in reachable {
+            name.hash(&mut h);
+            let cfg = self.get_cfg(&name);
+            cfg.hash(&mut h);
+            for ty in cfg.into_iter().flat_map(|c| &c.place_tys) {
+                self.named_types(ty, &mut named);
+            }
+        }
+
+        let mut named: Vec<_> = named.into_iter().collect();
+        named.sort();
+        for name in named {
+            name.hash(&mut h);
+            self.type_map.get(name).hash(&mut h);
+        }
+
+        h.finish()
+    }
+
+    // Every type name `ty` refers to, directly or through the definitions of others
+    fn named_types<'a>(&'a self, ty: &'a Type, named: &mut HashSet<&'a String>) {
+        match ty {
+            Type::Tuple(t) => t.elems.iter().for_each(|e| self.named_types(e, named)),
+            Type::Enum(e) => e.variants.iter().for_each(|v| self.named_types(v, named)),
+            Type::Named(n) => {
+                if named.insert(n) {
+                    if let Some(def) = self.type_map.get(n) {
+                        self.named_types(def, named);
+                    }
+                }
+            }
+        }
+    }
+
+    fn summary_path(&self, ident: &Ident) -> Option<PathBuf> {
+        let dir = self.cache_dir.as_ref()?;
+        Some(dir.join(format!("{ident}-{:016x}.deps", self.summary_key(ident))))
+    }
+
+    pub(super) fn load_summary(&self, ident: &Ident) -> Option<DepGraph> {
+        let contents = fs::read_to_string(self.summary_path(ident)?).ok()?;
+        parse_summary(&contents)
+    }
+
+    // The cache is only an optimization, so failing to write it isn't an error. Summaries
+    // of the function's older versions can't be used again, so they're removed.
+    pub(super) fn store_summary(&self, ident: &Ident, deps: &DepGraph) {
+        let Some(path) = self.summary_path(ident) else {
+            return;
+        };
+
+        if let Some(dir) = path.parent() {
+            let _ = fs::create_dir_all(dir);
+
+            let stale = fs::read_dir(dir)
+                .into_iter()
+                .flatten()
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|p| *p != path && summary_of(p).is_some_and(|name| name == ident.0));
+            for stale in stale {
+                let _ = fs::remove_file(stale);
+            }
+        }
+        let _ = fs::write(path, write_summary(deps));
+    }
+}
+
+/// The directory in `root` that a module's summaries are cached in. Each source file gets
+/// its own, so that modules with functions of the same name don't replace each other's
+/// summaries.
+pub fn module_cache_dir(root: &Path, source: &Path) -> PathBuf {
+    let source = fs::canonicalize(source).unwrap_or_else(|_| source.to_path_buf());
+    let mut h = StableHasher::new();
+    source.to_string_lossy().hash(&mut h);
+
+    let stem = source.file_stem().map_or("module".into(), |s| s.to_string_lossy());
+    root.join(format!("{stem}-{:016x}", h.finish()))
+}
+
+// The function a file in the cache is a summary of
+fn summary_of(path: &Path) -> Option<&str> {
+    let file = path.file_name()?.to_str()?.strip_suffix(".deps")?;
+    let (name, key) = file.rsplit_once('-')?;
+    (key.len() == 16 && key.chars().all(|c| c.is_ascii_hexdigit())).then_some(name)
+}
+
+// Only the fallback escapes of a summary are kept, since they are all that callers use
+fn write_summary(deps: &DepGraph) -> String {
+    let mut out = String::new();
+
+    for node in &deps.nodes {
+        let weight = match node.weight {
+            Perm::Opaque => "O",
+            Perm::Clear => "C",
+        };
+        let (kind, ds) = match &node.deps {
+            Deps::All(ds) => ("All", ds),
+            Deps::Xor(ds) => ("Xor", ds),
+        };
+
+        let _ = write!(out, "node {weight} {kind}");
+        for d in ds {
+            let _ = write!(out, " {d}");
+        }
+        out.push('\n');
+    }
+
+    for (label, places) in [("new", &deps.new_lives), ("alloced", &deps.alloced_args)] {
+        let mut places: Vec<_> = places.iter().collect();
+        places.sort();
+
+        out.push_str(label);
+        for p in places {
+            let _ = write!(out, " {p}");
+        }
+        out.push('\n');
+    }
+
+    let _ = writeln!(out, "effect {}", deps.effect.name());
+
+    match deps.escapes.get(&0) {
+        Some(Escape::Recursive) => out.push_str("fallback recursive\n"),
+        Some(Escape::Unconverged) => out.push_str("fallback unconverged\n"),
+        Some(Escape::AllocAlways) => out.push_str("fallback alloc_always\n"),
+        _ => {}
+    }
+
+    out
+}
+
+fn parse_summary(contents: &str) -> Option<DepGraph> {
+    let mut deps = DepGraph {
+        nodes: vec![],
+        new_lives: HashSet::new(),
+        alloced_args: HashSet::new(),
+        escapes: HashMap::new(),
+        effect: Effect::Pure,
+    };
+
+    for line in contents.lines() {
+        let mut words = line.split_whitespace();
+        match words.next()? {
+            "node" => {
+                let weight = match words.next()? {
+                    "O" => Perm::Opaque,
+                    "C" => Perm::Clear,
+                    _ => return None,
+                };
+                let kind = words.next()?;
+                let ds = words.map(|w| w.parse().ok()).collect::<Option<_>>()?;
+
+                deps.nodes.push(Node {
+                    weight,
+                    share: Share::Shared,
+                    deps: match kind {
+                        "All" => Deps::All(ds),
+                        "Xor" => Deps::Xor(ds),
+                        _ => return None,
+                    },
+                });
+            }
+            "new" => deps.new_lives = words.map(|w| w.parse().ok()).collect::<Option<_>>()?,
+            "alloced" => {
+                deps.alloced_args = words.map(|w| w.parse().ok()).collect::<Option<_>>()?
+            }
+            "effect" => deps.effect = Effect::from_name(words.next()?)?,
+            "fallback" => {
+                let escape = match words.next()? {
+                    "recursive" => Escape::Recursive,
+                    "unconverged" => Escape::Unconverged,
+                    "alloc_always" => Escape::AllocAlways,
+                    _ => return None,
+                };
+                deps.escapes.insert(0, escape);
+            }
+            _ => return None,
+        }
+    }
+
+    // make sure a corrupted file can't send us out of bounds
+    let len = deps.nodes.len();
+    let in_bounds = deps
+        .nodes
+        .iter()
+        .flat_map(|n| n.deps.get())
+        .chain(&deps.new_lives)
+        .chain(&deps.alloced_args)
+        .all(|&p| p < len);
+
+    (len != 0 && in_bounds).then_some(deps)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{cfg::test_util::context, types::Tuple};
+
+    #[test]
+    fn test_invalidate_dependents() {
+        let mut ctx = context(include_str!("../../../inputs/factorial.rs"));
+        for name in ["factorial", "one"] {
+            ctx.compute_depgraph(&name.into()).unwrap();
+        }
+
+        let times = ctx.get_cfg(&"times".into()).unwrap().clone();
+        ctx.update_cfg(times);
+
+        assert!(ctx.get_depgraph(&"factorial".into()).is_none());
+        assert!(ctx.get_depgraph(&"times".into()).is_none());
+        assert!(ctx.get_depgraph(&"times_inner".into()).is_some());
+        assert!(ctx.get_depgraph(&"one".into()).is_some());
+    }
+
+    #[test]
+    fn test_invalidate_heap_copies() {
+        let mut ctx = context("fn unit(c: ()) -> () { tuple() }");
+        let copy = ctx.heap_specialization(&"unit".into());
+        assert!(ctx.dependents(&"unit".into()).contains(&copy));
+
+        // the copy still returns on the heap once it's worked out again
+        ctx.invalidate(&"unit".into());
+        assert!(ctx.get_depgraph(&copy).is_none());
+        assert!(ctx.compute_depgraph(&copy).unwrap().nodes[0].allocated());
+        assert!(!ctx.compute_depgraph(&"unit".into()).unwrap().nodes[0].allocated());
+
+        // and follows the function it copies
+        let mut unit = ctx.get_cfg(&"unit".into()).unwrap().clone();
+        unit.arg_count = 0;
+        ctx.update_cfg(unit);
+        assert_eq!(ctx.get_cfg(&copy).unwrap().arg_count, 0);
+        assert!(ctx.get_depgraph(&copy).is_none());
+
+        ctx.remove_fn(&"unit".into());
+        assert!(ctx.get_cfg(&copy).is_none());
+    }
+
+    #[test]
+    fn test_summary_roundtrip() {
+        let mut deps = DepGraph::opaque();
+        deps.nodes.push(Node {
+            weight: Perm::Clear,
+            share: Share::Shared,
+            deps: Deps::Xor(vec![0]),
+        });
+        deps.alloced_args.insert(1);
+        deps.escapes.insert(0, Escape::Unconverged);
+        deps.effect = Effect::Effectful;
+
+        let parsed = parse_summary(&write_summary(&deps)).unwrap();
+        assert_eq!(parsed, deps);
+        assert_eq!(parsed.escapes, deps.escapes);
+    }
+
+    #[test]
+    fn test_stale_summaries_removed() {
+        let dir = std::env::temp_dir().join("perm-mem-test-stale-summaries");
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut ctx = context("fn unit(c: ()) -> () { tuple() }\nfn unit_pair(c: ()) -> () { unit(c) }");
+        ctx.cache_dir = Some(dir.clone());
+        let key = ctx.summary_key(&"unit".into());
+        for name in ["unit", "unit_pair"] {
+            ctx.compute_depgraph(&name.into()).unwrap();
+        }
+
+        // keys are the same for the same code, whatever built the compiler
+        let mut h = StableHasher::new();
+        h.write(b"a");
+        assert_eq!(h.finish(), 0xaf63dc4c8601ec8c);
+        assert_eq!(ctx.summary_key(&"unit".into()), key);
+
+        let mut unit = ctx.get_cfg(&"unit".into()).unwrap().clone();
+        unit.place_tys.push(Type::unit());
+        ctx.update_cfg(unit);
+        ctx.compute_depgraph(&"unit".into()).unwrap();
+
+        let mut files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(files.len(), 2);
+        assert!(files[0].starts_with("unit-") && !files[0].contains(&format!("{key:016x}")));
+        assert!(files[1].starts_with("unit_pair-"));
+    }
+
+    #[test]
+    fn test_key_follows_types() {
+        let src = "type Pair = ((), ());\nfn pair(c: ()) -> Pair { tuple(c, c) }";
+        let mut ctx = context(src);
+        let key = ctx.summary_key(&"pair".into());
+
+        // the same code can mean something else once a type it uses is defined differently
+        ctx.type_map.insert(
+            "Pair".to_string(),
+            Type::Tuple(Tuple {
+                elems: vec![Type::unit(), Type::unit(), Type::unit()],
+            }),
+        );
+        assert_ne!(ctx.summary_key(&"pair".into()), key);
+    }
+
+    #[test]
+    fn test_modules_cached_apart() {
+        let root = std::env::temp_dir().join("perm-mem-test-modules-cached-apart");
+        let _ = fs::remove_dir_all(&root);
+
+        let a = module_cache_dir(&root, Path::new("a/unit.rs"));
+        let b = module_cache_dir(&root, Path::new("b/unit.rs"));
+        assert_ne!(a, b);
+        assert!(a.file_name().unwrap().to_str().unwrap().starts_with("unit-"));
+
+        // building one module leaves the other's summary of a function with the same name
+        for (dir, body) in [(&a, "tuple()"), (&b, "c")] {
+            let mut ctx = context(&format!("fn unit(c: ()) -> () {{ {body} }}"));
+            ctx.cache_dir = Some(dir.clone());
+            ctx.compute_depgraph(&"unit".into()).unwrap();
+        }
+        for dir in [a, b] {
+            assert_eq!(fs::read_dir(dir).unwrap().count(), 1);
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::cfg::{Cfg, Phi, Statement, Terminator};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+/// A dataflow problem over a `Cfg`. Facts are kept at two points in every block: its
+/// start, before the phis, and its end, between the last statement and the terminator.
+pub trait Analysis {
+    type Fact: Clone + PartialEq;
+
+    const DIRECTION: Direction;
+
+    /// The fact at a point nothing has been learned about yet
+    fn bottom(&self, cfg: &Cfg) -> Self::Fact;
+
+    /// The fact flowing in from outside the cfg, which is into the first block going
+    /// forward or out of blocks without successors going backward
+    fn boundary(&self, cfg: &Cfg) -> Self::Fact {
+        self.bottom(cfg)
+    }
+
+    fn join(&self, into: &mut Self::Fact, other: &Self::Fact);
+
+    fn phis(&self, _fact: &mut Self::Fact, _block: usize, _phis: &[Phi]) {}
+
+    fn statement(&self, fact: &mut Self::Fact, point: (usize, usize), stmnt: &Statement);
+
+    fn terminator(&self, _fact: &mut Self::Fact, _block: usize, _terminator: &Terminator) {}
+
+    /// Facts that only hold along the edge from one block to another, such as the phi
+    /// options coming from that block
+    fn edge(&self, _fact: &mut Self::Fact, _cfg: &Cfg, _from: usize, _to: usize) {}
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Solution<F> {
+    pub start: Vec<F>,
+    pub end: Vec<F>,
+}
+
+pub fn solve<A: Analysis>(cfg: &Cfg, analysis: &A) -> Solution<A::Fact> {
+    let len = cfg.basic_blocks.len();
+    let bottom = analysis.bottom(cfg);
+    let mut solution = Solution {
+        start: vec![bottom.clone(); len],
+        end: vec![bottom.clone(); len],
+    };
+
+    let preds = cfg.predecessors();
+    let succs: Vec<_> = (0..len).map(|b| cfg.successors(b)).collect();
+
+    let mut worklist: VecDeque<_> = match A::DIRECTION {
+        Direction::Forward => (0..len).collect(),
+        Direction::Backward => (0..len).rev().collect(),
+    };
+    let mut queued = vec![true; len];
+
+    while let Some(b) = worklist.pop_front() {
+        queued[b] = false;
+        let block = &cfg.basic_blocks[b];
+
+        let changed = match A::DIRECTION {
+            Direction::Forward => {
+                let mut fact = match b {
+                    0 => analysis.boundary(cfg),
+                    _ => bottom.clone(),
+                };
+                for &pred in preds.get(&b).into_iter().flatten() {
+                    let mut incoming = solution.end[pred].clone();
+                    if let Some(t) = &cfg.basic_blocks[pred].terminator {
+                        analysis.terminator(&mut incoming, pred, t);
+                    }
+                    analysis.edge(&mut incoming, cfg, pred, b);
+                    analysis.join(&mut fact, &incoming);
+                }
+                solution.start[b] = fact.clone();
+
+                analysis.phis(&mut fact, b, &block.phi);
+                for (j, stmnt) in block.stmnts.iter().enumerate() {
+                    analysis.statement(&mut fact, (b, j), stmnt);
+                }
+
+                let changed = fact != solution.end[b];
+                solution.end[b] = fact;
+                changed.then_some(&succs[b])
+            }
+            Direction::Backward => {
+                let mut fact = match succs[b].is_empty() {
+                    true => analysis.boundary(cfg),
+                    false => bottom.clone(),
+                };
+                for &succ in &succs[b] {
+                    let mut outgoing = solution.start[succ].clone();
+                    analysis.edge(&mut outgoing, cfg, b, succ);
+                    analysis.join(&mut fact, &outgoing);
+                }
+                if let Some(t) = &block.terminator {
+                    analysis.terminator(&mut fact, b, t);
+                }
+                solution.end[b] = fact.clone();
+
+                for (j, stmnt) in block.stmnts.iter().enumerate().rev() {
+                    analysis.statement(&mut fact, (b, j), stmnt);
+                }
+                analysis.phis(&mut fact, b, &block.phi);
+
+                let changed = fact != solution.start[b];
+                solution.start[b] = fact;
+                changed.then(|| preds.get(&b)).flatten()
+            }
+        };
+
+        for &next in changed.into_iter().flatten() {
+            if !queued[next] {
+                queued[next] = true;
+                worklist.push_back(next);
+            }
+        }
+    }
+
+    solution
+}
+
+impl<F: Clone> Solution<F> {
+    /// The fact at every point of the cfg, keyed by `(block, index)`. `(b, j)` is the
+    /// point before statement `j`, `(b, len)` is the end of the block and `(b, -1)` is
+    /// its start, before the phis.
+    pub fn points<A: Analysis<Fact = F>>(
+        &self,
+        cfg: &Cfg,
+        analysis: &A,
+    ) -> HashMap<(usize, isize), F> {
+        let mut points = HashMap::new();
+
+        for (b, block) in cfg.basic_blocks.iter().enumerate() {
+            let len = block.stmnts.len() as isize;
+
+            match A::DIRECTION {
+                Direction::Forward => {
+                    let mut fact = self.start[b].clone();
+                    points.insert((b, -1), fact.clone());
+
+                    analysis.phis(&mut fact, b, &block.phi);
+                    for (j, stmnt) in block.stmnts.iter().enumerate() {
+                        points.insert((b, j as isize), fact.clone());
+                        analysis.statement(&mut fact, (b, j), stmnt);
+                    }
+                    points.insert((b, len), fact);
+                }
+                Direction::Backward => {
+                    let mut fact = self.end[b].clone();
+                    points.insert((b, len), fact.clone());
+
+                    for (j, stmnt) in block.stmnts.iter().enumerate().rev() {
+                        analysis.statement(&mut fact, (b, j), stmnt);
+                        points.insert((b, j as isize), fact.clone());
+                    }
+                    analysis.phis(&mut fact, b, &block.phi);
+                    points.insert((b, -1), fact);
+                }
+            }
+        }
+
+        points
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::cfg::{analysis::lva::LVA, test_util::context};
+
+    // Places that may have been assigned by each point
+    struct Assigned;
+
+    impl Analysis for Assigned {
+        type Fact = HashSet<usize>;
+
+        const DIRECTION: Direction = Direction::Forward;
+
+        fn bottom(&self, _cfg: &Cfg) -> Self::Fact {
+            HashSet::new()
+        }
+
+        fn boundary(&self, cfg: &Cfg) -> Self::Fact {
+            (1..=cfg.arg_count).collect()
+        }
+
+        fn join(&self, into: &mut Self::Fact, other: &Self::Fact) {
+            into.extend(other);
+        }
+
+        fn phis(&self, fact: &mut Self::Fact, _block: usize, phis: &[Phi]) {
+            fact.extend(phis.iter().map(|phi| phi.place));
+        }
+
+        fn statement(&self, fact: &mut Self::Fact, _point: (usize, usize), stmnt: &Statement) {
+            if let Statement::Assign(a) = stmnt {
+                fact.insert(a.place);
+            }
+        }
+    }
+
+    #[test]
+    fn test_forward_and_backward() {
+        let ctx = context(include_str!("../../../inputs/gen_list.rs"));
+        let cfg = ctx.get_cfg(&"generate".into()).unwrap();
+
+        // everything is assigned by the time the returning block ends
+        let assigned = solve(cfg, &Assigned);
+        let ret = (0..cfg.basic_blocks.len())
+            .find(|&b| matches!(cfg.basic_blocks[b].terminator, Some(Terminator::Return(_))))
+            .unwrap();
+        assert_eq!(assigned.end[ret], (1..cfg.place_tys.len()).collect());
+
+        // only the argument is live when the function starts
+        let lva = LVA::analyze(cfg);
+        assert_eq!(lva.blocks[0].live_in, HashSet::from_iter([1]));
+        let points = lva.point_lva(cfg);
+        assert_eq!(points[&(0, -1)], lva.blocks[0].live_in);
+    }
+}
@@ -1,8 +1,14 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+};
 
-use crate::cfg::{Cfg, Statement, Terminator, Value};
+use crate::{
+    ast::Ident,
+    cfg::{Cfg, DebugNames, Statement, Terminator, Value},
+};
 
-use super::Context;
+use super::{alias::Aliases, effects::Effect, Context};
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Perm {
@@ -10,16 +16,30 @@ pub enum Perm {
     Clear,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug)]
 pub struct DepGraph {
     pub nodes: Vec<Node>,
     pub new_lives: HashSet<usize>,
     pub alloced_args: HashSet<usize>,
+    /// Why each heap node is on the heap. Only the first reason found for a node is kept,
+    /// so a node's own reason, such as being returned or made by a callee, is recorded
+    /// before any it inherits from a heap value holding it. A callee storing an argument
+    /// is found before the argument is seen inside a heap value here.
+    pub escapes: HashMap<usize, Escape>,
+    pub effect: Effect,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+/// Whether a reference is the only one to its value, assumed shared until shown otherwise.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Share {
+    Exclusive,
+    Shared,
+}
+
+#[derive(Clone, Debug)]
 pub struct Node {
     pub weight: Perm,
+    pub share: Share,
     pub deps: Deps,
 }
 
@@ -29,10 +49,39 @@ pub enum Deps {
     Xor(Vec<usize>),
 }
 
+/// The reason a node was made opaque.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Escape {
+    Returned,
+    Element {
+        parent: usize,
+        index: usize,
+    },
+    Alternative {
+        parent: usize,
+    },
+    Callee {
+        func: Ident,
+        root: bool,
+        recursive: bool,
+        cause: Option<Box<Escape>>,
+    },
+    AllocedArg {
+        func: Ident,
+        arg: usize,
+    },
+    Promoted,
+    MixedReturn,
+    Recursive,
+    Unconverged,
+    AllocAlways,
+}
+
 impl Node {
     pub fn leaf(weight: Perm) -> Self {
         Self {
             weight,
+            share: Share::Shared,
             deps: Deps::All(vec![]),
         }
     }
@@ -43,6 +92,31 @@ impl Node {
             Perm::Clear => false,
         }
     }
+
+    pub fn exclusive(&self) -> bool {
+        match self.share {
+            Share::Exclusive => true,
+            Share::Shared => false,
+        }
+    }
+}
+
+// Shares are only assigned for rendering and aren't part of a summary, so they don't take
+// part in equality
+impl PartialEq for Node {
+    fn eq(&self, other: &Self) -> bool {
+        self.weight == other.weight && self.deps == other.deps
+    }
+}
+
+// Escapes are only an explanation of the graph, so they don't take part in equality
+impl PartialEq for DepGraph {
+    fn eq(&self, other: &Self) -> bool {
+        self.nodes == other.nodes
+            && self.new_lives == other.new_lives
+            && self.alloced_args == other.alloced_args
+            && self.effect == other.effect
+    }
 }
 
 impl Deps {
@@ -59,12 +133,82 @@ impl Deps {
     }
 }
 
+impl Escape {
+    // Fallbacks describe a whole summary rather than a single place in it
+    pub fn is_fallback(&self) -> bool {
+        matches!(
+            self,
+            Self::Recursive | Self::Unconverged | Self::AllocAlways
+        )
+    }
+
+    pub fn parent(&self) -> Option<usize> {
+        match self {
+            Self::Element { parent, .. } | Self::Alternative { parent } => Some(*parent),
+            _ => None,
+        }
+    }
+
+    fn remapped(mut self, remap: &[usize]) -> Self {
+        if let Self::Element { parent, .. } | Self::Alternative { parent } = &mut self {
+            *parent = remap[*parent];
+        }
+
+        self
+    }
+
+    fn describe(&self, f: &mut String) {
+        let _ = match self {
+            Self::Returned => write!(f, "is returned"),
+            Self::Element { parent, index } => write!(f, "is element {index} of _{parent}"),
+            Self::Alternative { parent } => write!(f, "may be the value of _{parent}"),
+            Self::Callee {
+                func,
+                root,
+                recursive,
+                cause,
+            } => {
+                let _ = match root {
+                    true => write!(f, "is returned by {func}"),
+                    false => write!(f, "is part of the value returned by {func}"),
+                };
+
+                if *recursive {
+                    let _ = write!(f, " (recursive summary)");
+                }
+
+                if let Some(cause) = cause {
+                    let _ = write!(f, ", whose result ");
+                    cause.describe(f);
+                }
+
+                Ok(())
+            }
+            Self::Promoted => write!(f, "is a heap copy made where it merges with heap values"),
+            Self::MixedReturn => write!(f, "is returned alongside values on the heap"),
+            Self::AllocedArg { func, arg } => {
+                write!(f, "is stored on the heap by {func} as its argument _{arg}")
+            }
+            Self::Unconverged => {
+                write!(f, "belongs to a recursive summary that did not converge")
+            }
+            Self::Recursive => write!(
+                f,
+                "belongs to a recursive summary, which the allocation policy puts on the heap"
+            ),
+            Self::AllocAlways => write!(f, "falls under the always-heap allocation policy"),
+        };
+    }
+}
+
 impl DepGraph {
     pub fn opaque() -> Self {
         Self {
             nodes: vec![Node::leaf(Perm::Opaque)],
             new_lives: HashSet::from_iter([0]),
             alloced_args: HashSet::new(),
+            escapes: HashMap::new(),
+            effect: Effect::Allocates,
         }
     }
 
@@ -73,17 +217,28 @@ impl DepGraph {
             nodes: vec![Node::leaf(Perm::Clear); cfg.place_tys.len()],
             new_lives: HashSet::new(),
             alloced_args: HashSet::new(),
+            escapes: HashMap::new(),
+            effect: Effect::Pure,
         };
 
         this.nodes[0].deps = Deps::Xor(vec![]);
         if ret_alloced {
+            let escape = ctx
+                .get_depgraph(&cfg.name)
+                .and_then(|d| d.escapes.get(&0))
+                .filter(|e| e.is_fallback());
+
             this.nodes[0].weight = Perm::Opaque;
+            this.escapes
+                .insert(0, escape.cloned().unwrap_or(Escape::Returned));
         }
 
         // Add statements-induced dependencies
+        let mut promoted = vec![];
         for stmnt in cfg.statements() {
             match stmnt {
                 Statement::Assign(a) => match &a.value {
+                    Value::Place(src) if a.allocate => promoted.push((a.place, *src)),
                     Value::Place(src) => {
                         this.nodes[a.place].deps = Deps::Xor(vec![*src]);
                     }
@@ -91,10 +246,16 @@ impl DepGraph {
                         "tuple" => {
                             this.nodes[a.place].deps = Deps::All(args.clone());
                         }
-                        "invent" | "print" => {}
+                        "invent" => {}
+                        "print" => this.effect = Effect::Effectful,
                         name => {
                             if let Some(fdeps) = ctx.compute_depgraph(func) {
-                                this.merge_in(a.place, args, ctx.get_cfg(func).unwrap(), fdeps);
+                                let recursive = func == &cfg.name;
+                                if !recursive {
+                                    this.effect = this.effect.max(fdeps.effect);
+                                }
+                                let child_cfg = ctx.get_cfg(func).unwrap();
+                                this.merge_in(a.place, args, child_cfg, fdeps, recursive);
                             } else if ctx.type_map.contains_key(name) {
                                 this.nodes[a.place].deps = Deps::All(args.clone());
                             } else {
@@ -124,31 +285,32 @@ impl DepGraph {
             }
         }
 
-        // propogate allocations
-        for c in this.preorder_all() {
-            if this.nodes[c].allocated() {
-                for dep in this.nodes[c].deps.get().clone() {
-                    this.nodes[dep].weight = Perm::Opaque;
-                }
-            }
+        // Heap copies hold whatever their source could hold
+        for (place, src) in promoted {
+            this.nodes[place].weight = Perm::Opaque;
+            this.nodes[place].deps = Deps::All(this.contents(src));
+            this.escapes.insert(place, Escape::Promoted);
         }
 
-        // calculate every node that's reachable from the returned node
-        let reachable_nodes = {
-            let mut nodes = HashSet::new();
-            let mut len = nodes.len();
+        this.propogate_allocations();
 
-            nodes.insert(0);
-            while nodes.len() != len {
-                len = nodes.len();
+        // a function returns its value one way, so if any alternative of the
+        // returned value lives on the heap, they all have to
+        if !this.nodes[0].allocated() && this.heap_stored(0) {
+            this.nodes[0].weight = Perm::Opaque;
+            this.escapes.insert(0, Escape::MixedReturn);
+            this.propogate_allocations();
+        }
 
-                for n in nodes.clone() {
-                    nodes.extend(this.nodes[n].deps.get());
-                }
-            }
+        // anything put on the heap here, rather than handed in, is an allocation
+        if (0..this.nodes.len())
+            .any(|n| this.nodes[n].allocated() && !(1..=cfg.arg_count).contains(&n))
+        {
+            this.effect = this.effect.max(Effect::Allocates);
+        }
 
-            nodes
-        };
+        // calculate every node that's reachable from the returned node
+        let reachable_nodes = this.aliases().reachable_from_return();
 
         // populate new lives and alloced args
         for (i, node) in this.nodes.iter().enumerate() {
@@ -168,12 +330,45 @@ impl DepGraph {
         this
     }
 
+    fn propogate_allocations(&mut self) {
+        for c in self.preorder_all() {
+            if self.nodes[c].allocated() {
+                for (index, dep) in self.nodes[c].deps.get().clone().into_iter().enumerate() {
+                    self.nodes[dep].weight = Perm::Opaque;
+                    self.escapes.entry(dep).or_insert(match self.nodes[c].deps {
+                        Deps::All(_) => Escape::Element { parent: c, index },
+                        Deps::Xor(_) => Escape::Alternative { parent: c },
+                    });
+                }
+            }
+        }
+    }
+
+    /// Whether a node is stored behind a pointer, which is the case for allocated
+    /// nodes and for aliases that may be one.
+    pub fn heap_stored(&self, node: usize) -> bool {
+        match &self.nodes[node].deps {
+            _ if self.nodes[node].allocated() => true,
+            Deps::Xor(alts) => alts.iter().any(|&a| self.heap_stored(a)),
+            Deps::All(_) => false,
+        }
+    }
+
+    // Everything a node could directly hold, looking through aliases
+    fn contents(&self, node: usize) -> Vec<usize> {
+        match &self.nodes[node].deps {
+            Deps::All(deps) => deps.clone(),
+            Deps::Xor(alts) => alts.iter().flat_map(|&a| self.contents(a)).collect(),
+        }
+    }
+
     pub fn merge_in(
         &mut self,
         parent: usize,
         passed_args: &[usize],
         child_cfg: &Cfg,
         child_graph: DepGraph,
+        recursive: bool,
     ) {
         assert_eq!(child_cfg.arg_count, passed_args.len());
 
@@ -192,6 +387,20 @@ impl DepGraph {
             }
 
             if !child_args.contains(&place) {
+                if node.allocated() {
+                    let cause = child_graph.escapes.get(&place).filter(|e| e.is_fallback());
+
+                    self.escapes.insert(
+                        remap_place,
+                        Escape::Callee {
+                            func: child_cfg.name.clone(),
+                            root: place == 0,
+                            recursive,
+                            cause: cause.cloned().map(Box::new),
+                        },
+                    );
+                }
+
                 self.nodes[remap_place] = node;
             }
         }
@@ -199,6 +408,10 @@ impl DepGraph {
         for arg in child_graph.alloced_args {
             let remap_arg = self.remap_place(arg, &mut remap);
             self.nodes[remap_arg].weight = Perm::Opaque;
+            self.escapes.entry(remap_arg).or_insert(Escape::AllocedArg {
+                func: child_cfg.name.clone(),
+                arg,
+            });
         }
 
         // self.new_lives.extend(
@@ -352,88 +565,205 @@ impl DepGraph {
         }
 
         self.new_lives = self.new_lives.iter().map(|&l| remap[l]).collect();
+        self.escapes = std::mem::take(&mut self.escapes)
+            .into_iter()
+            .filter(|(n, e)| {
+                reachable.contains(n) && e.parent().is_none_or(|p| reachable.contains(&p))
+            })
+            .map(|(n, e)| (remap[n], e.remapped(&remap)))
+            .collect();
     }
 
-    pub fn flatten_to_counters(&self, nodes: impl IntoIterator<Item = usize>) -> Vec<usize> {
-        let mut out = vec![0; self.nodes.len()];
+    /// Describes the chain of reasons that caused a node to be heap allocated. Each link is
+    /// the first reason `escapes` recorded, which is one way the node ends up on the heap
+    /// but not necessarily the shortest.
+    pub fn explain(&self, node: usize) -> String {
+        if !self.nodes[node].allocated() {
+            return format!("_{node} is not heap allocated");
+        }
 
-        for node in nodes {
-            out[node] += 1;
+        let mut out = format!("_{node} is heap because it ");
+        let mut visited = HashSet::new();
+        let mut focus = node;
+        while visited.insert(focus) {
+            let Some(escape) = self.escapes.get(&focus) else {
+                out.push_str("was assumed to be opaque");
+                break;
+            };
 
-            match &self.nodes[node].deps {
-                Deps::All(deps) => {
-                    let ctrs = self.flatten_to_counters(deps.iter().copied());
-                    for (o, a) in out.iter_mut().zip(ctrs) {
-                        *o += a;
-                    }
-                }
+            escape.describe(&mut out);
 
-                Deps::Xor(deps) => {
-                    let ctrs = self.flatten_to_counters(deps.iter().copied());
-                    for (o, a) in out.iter_mut().zip(ctrs) {
-                        *o = a.max(*o);
-                    }
+            match escape.parent() {
+                Some(parent) => {
+                    out.push_str(", which ");
+                    focus = parent;
                 }
+                None => break,
             }
         }
 
         out
     }
 
-    pub fn flatten_to_counters_ignorant(
-        &self,
-        nodes: impl IntoIterator<Item = usize>,
-    ) -> Vec<usize> {
+    /// Counts, for every node, how many references to it are held by the given live
+    /// nodes. A stack value holds its dependencies directly, so its references are counted
+    /// once per path, while an allocated cell owns exactly one reference to each of its
+    /// dependencies no matter how many live references reach the cell itself.
+    pub fn live_ref_counts(&self, live: impl IntoIterator<Item = usize>) -> Vec<usize> {
+        let mut memo = HashMap::new();
         let mut out = vec![0; self.nodes.len()];
 
-        for node in nodes {
-            // very ignorant >._.<
-            if self.nodes[node].allocated() {
+        let live: Vec<_> = live.into_iter().collect();
+        for &node in &live {
+            add_counters(&mut out, self.held_refs(node, &mut memo));
+        }
+
+        // every cell reachable from a live node keeps its own references alive
+        let mut visited = HashSet::new();
+        let mut stack = live;
+        while let Some(node) = stack.pop() {
+            if !visited.insert(node) {
                 continue;
             }
 
-            out[node] += 1;
+            let deps = self.nodes[node].deps.get();
+            if self.is_cell(node) {
+                for &dep in deps {
+                    add_counters(&mut out, self.held_refs(dep, &mut memo));
+                }
+            }
 
-            match &self.nodes[node].deps {
-                Deps::All(deps) => {
-                    let ctrs = self.flatten_to_counters_ignorant(deps.iter().copied());
-                    for (o, a) in out.iter_mut().zip(ctrs) {
-                        *o += a;
-                    }
+            stack.extend(deps);
+        }
+
+        out
+    }
+
+    /// The share of every node referenced by the given live places, or `None` for nodes that
+    /// aren't referenced at all. Anything that may be an argument is always shared since the
+    /// caller may keep it. `aliases` are this graph's, shared between the queries of every
+    /// program point.
+    pub fn shares(
+        &self,
+        aliases: &Aliases,
+        live: &HashSet<usize>,
+        arg_count: usize,
+    ) -> Vec<Option<Share>> {
+        let ctrs = self.live_ref_counts(live.iter().copied());
+        let mut shares: Vec<_> = ctrs
+            .iter()
+            .enumerate()
+            .map(|(i, &ctr)| match ctr {
+                0 => None,
+                1 if !(1..=arg_count).any(|arg| aliases.may_alias(i, arg, arg_count)) => {
+                    Some(Share::Exclusive)
                 }
+                _ => Some(Share::Shared),
+            })
+            .collect();
 
-                Deps::Xor(deps) => {
-                    let ctrs = self.flatten_to_counters_ignorant(deps.iter().copied());
-                    for (o, a) in out.iter_mut().zip(ctrs) {
-                        *o = a.max(*o);
-                    }
+        // anything reachable through a shared value is shared as well
+        let mut stack: Vec<_> = (0..shares.len())
+            .filter(|&i| shares[i] == Some(Share::Shared))
+            .collect();
+        while let Some(shared) = stack.pop() {
+            for &dep in self.nodes[shared].deps.get() {
+                if shares[dep] == Some(Share::Exclusive) {
+                    shares[dep] = Some(Share::Shared);
+                    stack.push(dep);
                 }
             }
         }
 
-        out
+        shares
     }
 
-    pub fn non_ret_new_lives(&self) -> HashSet<usize> {
-        let mut new_lives = self.new_lives.clone();
-        let mut stack = vec![0];
+    /// Marks every node that is exclusive at each program point it is referenced from.
+    pub fn assign_shares(&mut self, cfg: &Cfg, plva: &HashMap<(usize, isize), HashSet<usize>>) {
+        let mut exclusive: Vec<_> = (0..self.nodes.len())
+            .map(|i| !(1..=cfg.arg_count).contains(&i))
+            .collect();
+        let aliases = self.aliases();
+        for live in plva.values() {
+            for (i, share) in self.shares(&aliases, live, cfg.arg_count).into_iter().enumerate() {
+                if share == Some(Share::Shared) {
+                    exclusive[i] = false;
+                }
+            }
+        }
 
-        while let Some(new) = stack.pop() {
-            let Deps::Xor(deps) = &self.nodes[new].deps else {
-                new_lives.remove(&new);
-                continue;
+        for (node, exclusive) in self.nodes.iter_mut().zip(exclusive) {
+            node.share = match exclusive {
+                true => Share::Exclusive,
+                false => Share::Shared,
             };
+        }
+    }
 
-            stack.extend(deps)
+    // The references held by one reference to `node`, stopping at allocated cells
+    fn held_refs<'a>(&self, node: usize, memo: &'a mut HashMap<usize, Vec<usize>>) -> &'a [usize] {
+        if !memo.contains_key(&node) {
+            let mut out = vec![0; self.nodes.len()];
+            out[node] = 1;
+
+            if !self.is_cell(node) {
+                match &self.nodes[node].deps {
+                    Deps::All(deps) => {
+                        for &dep in deps {
+                            add_counters(&mut out, self.held_refs(dep, memo));
+                        }
+                    }
+                    Deps::Xor(deps) => {
+                        let mut alts = vec![0; self.nodes.len()];
+                        for &dep in deps {
+                            for (a, &c) in alts.iter_mut().zip(self.held_refs(dep, memo)) {
+                                *a = c.max(*a);
+                            }
+                        }
+
+                        add_counters(&mut out, &alts);
+                    }
+                }
+            }
+
+            memo.insert(node, out);
         }
 
-        new_lives
+        &memo[&node]
+    }
+
+    // Xor nodes are aliases of their alternatives, so only allocated All nodes own their deps
+    fn is_cell(&self, node: usize) -> bool {
+        self.nodes[node].allocated() && matches!(self.nodes[node].deps, Deps::All(_))
+    }
+
+    pub fn non_ret_new_lives(&self) -> HashSet<usize> {
+        let returned = self.aliases().alternatives(0);
+        self.new_lives.difference(&returned).copied().collect()
+    }
+}
+
+fn add_counters(out: &mut [usize], ctrs: &[usize]) {
+    for (o, c) in out.iter_mut().zip(ctrs) {
+        *o += c;
     }
 }
 
 type Nd = usize;
 type Ed = (usize, usize);
-impl<'a> dot::Labeller<'a, Nd, Ed> for DepGraph {
+/// A dependency graph along with the names of its function's places, for rendering.
+pub struct Named<'a> {
+    pub deps: &'a DepGraph,
+    pub names: &'a DebugNames,
+}
+
+impl DepGraph {
+    pub fn named<'a>(&'a self, names: &'a DebugNames) -> Named<'a> {
+        Named { deps: self, names }
+    }
+}
+
+impl<'a> dot::Labeller<'a, Nd, Ed> for Named<'_> {
     fn graph_id(&self) -> dot::Id<'_> {
         dot::Id::new("DependencyGraph").unwrap()
     }
@@ -443,27 +773,31 @@ impl<'a> dot::Labeller<'a, Nd, Ed> for DepGraph {
     }
 
     fn node_label(&self, n: &Nd) -> dot::LabelText<'_> {
-        let label = match self.nodes[*n].deps {
-            Deps::All(_) => format!("_{n}"),
-            Deps::Xor(_) => format!("Xor(_{n})"),
+        let name = self.names.show(*n);
+        let mut label = match self.deps.nodes[*n].deps {
+            Deps::All(_) => name,
+            Deps::Xor(_) => format!("Xor({name})"),
         };
+        if self.deps.nodes[*n].exclusive() {
+            label.push_str(" X");
+        }
 
         dot::LabelText::html::<String>(label.into())
     }
 
     fn node_style(&self, n: &Nd) -> dot::Style {
-        match self.nodes[*n].weight {
+        match self.deps.nodes[*n].weight {
             Perm::Opaque => dot::Style::Filled,
             Perm::Clear => dot::Style::None,
         }
     }
 
     fn node_color(&self, node: &Nd) -> Option<dot::LabelText<'_>> {
-        if let Deps::Xor(_) = self.nodes[*node].deps {
+        if let Deps::Xor(_) = self.deps.nodes[*node].deps {
             return Some(dot::LabelText::LabelStr("grey".into()));
         }
 
-        Some(match self.new_lives.contains(node) {
+        Some(match self.deps.new_lives.contains(node) {
             true => dot::LabelText::LabelStr("orange".into()),
             false => dot::LabelText::LabelStr("green".into()),
         })
@@ -474,27 +808,28 @@ impl<'a> dot::Labeller<'a, Nd, Ed> for DepGraph {
     }
 
     fn edge_style(&self, (a, _): &Ed) -> dot::Style {
-        match self.nodes[*a].deps {
+        match self.deps.nodes[*a].deps {
             Deps::Xor(_) => dot::Style::Dashed,
             Deps::All(_) => dot::Style::None,
         }
     }
 
     fn edge_color(&self, (a, _): &Ed) -> Option<dot::LabelText<'_>> {
-        Some(match self.nodes[*a].deps {
+        Some(match self.deps.nodes[*a].deps {
             Deps::Xor(_) => dot::LabelText::LabelStr("grey".into()),
             Deps::All(_) => dot::LabelText::LabelStr("black".into()),
         })
     }
 }
 
-impl<'a> dot::GraphWalk<'a, Nd, Ed> for DepGraph {
+impl<'a> dot::GraphWalk<'a, Nd, Ed> for Named<'_> {
     fn nodes(&self) -> dot::Nodes<'a, Nd> {
-        (0..self.nodes.len()).collect()
+        (0..self.deps.nodes.len()).collect()
     }
 
     fn edges(&self) -> dot::Edges<'_, Ed> {
-        self.nodes
+        self.deps
+            .nodes
             .iter()
             .enumerate()
             .flat_map(|(i, n)| n.deps.get().iter().map(move |&d| (i, d)))
@@ -509,3 +844,96 @@ impl<'a> dot::GraphWalk<'a, Nd, Ed> for DepGraph {
         e.1
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cfg::test_util::context;
+
+    // `push` returns either a generated list or its argument consed onto, so it has to
+    // return on the heap, and `run` hands it a list of its own
+    fn pushed() -> Context {
+        context(&format!(
+            "{}\nfn cons(l: List) -> List {{ List(tuple(tuple(), l)) }}\n\
+             fn push(l: List, c: ()) -> List {{ if c {{ generate(c) }} else {{ cons(l) }} }}\n\
+             fn run(c: ()) -> () {{ let l = List(tuple()); let p = push(l, c); print(p) }}\n\
+             fn empty(c: ()) -> List {{ List(tuple()) }}\n\
+             fn pick(c: ()) -> List {{ let l = empty(c); let p = push(l, c); if c {{ p }} else {{ l }} }}",
+            include_str!("../../../inputs/gen_list.rs")
+        ))
+    }
+
+    fn managed_deps(ctx: &mut Context, name: &str) -> DepGraph {
+        let alloced = ctx.compute_depgraph(&name.into()).unwrap().nodes[0].allocated();
+        let cfg = ctx.get_cfg(&name.into()).unwrap().clone();
+        DepGraph::from_cfg(ctx, &cfg, alloced)
+    }
+
+    #[test]
+    fn test_explain_returned() {
+        let mut ctx = pushed();
+        let deps = managed_deps(&mut ctx, "push");
+
+        assert_eq!(deps.explain(0), "_0 is heap because it is returned");
+        assert_eq!(
+            deps.explain(6),
+            "_6 is heap because it is element 0 of _4, which may be the value of _5, \
+             which may be the value of _0, which is returned"
+        );
+        // the argument is consed onto the returned list
+        assert_eq!(
+            deps.explain(1),
+            "_1 is heap because it is element 1 of _6, which is element 0 of _4, \
+             which may be the value of _5, which may be the value of _0, which is returned"
+        );
+        assert_eq!(deps.explain(2), "_2 is not heap allocated");
+    }
+
+    #[test]
+    fn test_explain_alloced_arg() {
+        let mut ctx = pushed();
+        let deps = managed_deps(&mut ctx, "run");
+
+        assert_eq!(
+            deps.explain(3),
+            "_3 is heap because it is stored on the heap by push as its argument _1"
+        );
+        assert_eq!(
+            deps.explain(2),
+            "_2 is heap because it is element 0 of _3, which is stored on the heap by push \
+             as its argument _1"
+        );
+        assert_eq!(deps.explain(4), "_4 is heap because it is returned by push");
+
+        // the list could be returned as well, but push storing it is found first
+        let deps = managed_deps(&mut ctx, "pick");
+        assert!(matches!(&deps.nodes[4].deps, Deps::Xor(alts) if alts.contains(&2)));
+        assert_eq!(
+            deps.explain(2),
+            "_2 is heap because it is stored on the heap by push as its argument _1"
+        );
+    }
+
+    #[test]
+    fn test_explain_unconverged() {
+        let mut ctx = pushed();
+
+        // a summary that gave up on converging allocates its result
+        let mut gave_up = DepGraph::opaque();
+        gave_up.escapes.insert(0, Escape::Unconverged);
+        ctx.set_depgraph(&"generate".into(), gave_up);
+
+        let deps = managed_deps(&mut ctx, "push");
+        assert_eq!(
+            deps.explain(3),
+            "_3 is heap because it is returned by generate, whose result belongs to a \
+             recursive summary that did not converge"
+        );
+
+        let deps = managed_deps(&mut ctx, "generate");
+        assert_eq!(
+            deps.explain(0),
+            "_0 is heap because it belongs to a recursive summary that did not converge"
+        );
+    }
+}
@@ -0,0 +1,54 @@
+use super::Context;
+use crate::ast::Ident;
+
+/// What running a function can do besides computing its result, ordered from the least
+/// to the most observable.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Effect {
+    /// Only computes its result, so the call can be removed or merged with an equal one
+    #[default]
+    Pure,
+    /// Allocates heap memory, which is unobservable if the result goes unused
+    Allocates,
+    /// Prints or calls something outside of the module, so it has to stay where it is
+    Effectful,
+}
+
+impl Effect {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Pure => "pure",
+            Self::Allocates => "allocates",
+            Self::Effectful => "effectful",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Self::Pure, Self::Allocates, Self::Effectful]
+            .into_iter()
+            .find(|e| e.name() == name)
+    }
+
+    /// Whether a call with this effect can be deleted when its result is unused.
+    pub fn removable(self) -> bool {
+        self != Self::Effectful
+    }
+}
+
+impl Context {
+    /// The effect of calling `func`, which covers builtins, constructors, and the summaries
+    /// of user functions. Anything else is assumed to be an extern call.
+    pub fn call_effect(&mut self, func: &Ident) -> Effect {
+        match func.0.as_str() {
+            "print" => Effect::Effectful,
+            "invent" | "tuple" => Effect::Pure,
+            name if self.type_map.contains_key(name) && !self.fns.contains_key(func) => {
+                Effect::Pure
+            }
+            _ => self
+                .compute_depgraph(func)
+                .map(|d| d.effect)
+                .unwrap_or(Effect::Effectful),
+        }
+    }
+}
@@ -1,154 +1,90 @@
 use std::collections::{HashMap, HashSet};
 
-use crate::cfg::{
-    Value, {BasicBlock, Cfg, Statement, Terminator},
-};
+use super::dataflow::{self, Analysis, Direction, Solution};
+use crate::cfg::{Cfg, Phi, Statement, Terminator, Value};
 
 #[derive(Clone, Default, Debug, PartialEq, Eq)]
 pub struct LVABlock {
-    pub uevar: HashSet<usize>,
-    pub def: HashSet<usize>,
-    pub phi_out: HashSet<usize>,
-    pub phi_in: HashSet<usize>,
-
     pub live_in: HashSet<usize>,
     pub live_out: HashSet<usize>,
 }
 
-impl LVABlock {
-    fn new<'a>(i: usize, bb: &BasicBlock, succs: impl Iterator<Item = &'a BasicBlock>) -> Self {
-        let mut this = Self::default();
-
-        this.phi_out
-            .extend(succs.flat_map(|b| &b.phi).map(|p| p.opts[&i]));
-
-        this.phi_in
-            .extend(bb.phi.iter().flat_map(|p| p.opts.values()));
-        this.def.extend(bb.phi.iter().map(|p| p.place));
-
-        for stmnt in &bb.stmnts {
-            match &stmnt {
-                Statement::Assign(assign) => {
-                    match &assign.value {
-                        Value::Place(p) => this.access(*p),
-                        Value::Call { args, .. } => {
-                            for &arg in args {
-                                this.access(arg)
-                            }
-                        }
-                    }
-
-                    this.def.insert(assign.place);
-                }
-                Statement::Nop
-                | Statement::Deallocate(_)
-                | Statement::Dup(_)
-                | Statement::Drop(_) => {}
-            };
-        }
+#[derive(Debug, Clone, PartialEq)]
+pub struct LVA {
+    pub blocks: Vec<LVABlock>,
+}
+
+// Phi options are only live along the edge from the block they come from
+struct Liveness;
+
+impl Analysis for Liveness {
+    type Fact = HashSet<usize>;
 
-        if let Some(Terminator::IfElse { cond, .. }) = &bb.terminator {
-            this.access(*cond);
+    const DIRECTION: Direction = Direction::Backward;
+
+    fn bottom(&self, _cfg: &Cfg) -> Self::Fact {
+        HashSet::new()
+    }
+
+    fn join(&self, into: &mut Self::Fact, other: &Self::Fact) {
+        into.extend(other);
+    }
+
+    fn phis(&self, fact: &mut Self::Fact, _block: usize, phis: &[Phi]) {
+        for phi in phis {
+            fact.remove(&phi.place);
         }
+    }
+
+    fn statement(&self, fact: &mut Self::Fact, _point: (usize, usize), stmnt: &Statement) {
+        let Statement::Assign(a) = stmnt else {
+            return;
+        };
 
-        this
+        fact.remove(&a.place);
+        match &a.value {
+            Value::Place(p) => fact.extend([*p]),
+            Value::Call { args, .. } => fact.extend(args),
+        }
     }
 
-    fn access(&mut self, place: usize) {
-        if !self.def.contains(&place) {
-            self.uevar.insert(place);
+    fn terminator(&self, fact: &mut Self::Fact, _block: usize, terminator: &Terminator) {
+        match terminator {
+            Terminator::Return(p) => fact.extend([*p]),
+            Terminator::IfElse { cond, .. } => fact.extend([*cond]),
+            Terminator::Goto(_) => {}
         }
     }
-}
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct LVA {
-    pub blocks: Vec<LVABlock>,
+    fn edge(&self, fact: &mut Self::Fact, cfg: &Cfg, from: usize, to: usize) {
+        fact.extend(
+            cfg.basic_blocks[to]
+                .phi
+                .iter()
+                .filter_map(|phi| phi.opts.get(&from)),
+        );
+    }
 }
 
 impl LVA {
     pub fn analyze(cfg: &Cfg) -> Self {
-        let mut blocks: Vec<_> = cfg
-            .basic_blocks
-            .iter()
-            .enumerate()
-            .map(|(i, b)| {
-                LVABlock::new(
-                    i,
-                    b,
-                    cfg.successors(i).iter().map(|&i| &cfg.basic_blocks[i]),
-                )
-            })
+        let solution = dataflow::solve(cfg, &Liveness);
+        let blocks = solution
+            .start
+            .into_iter()
+            .zip(solution.end)
+            .map(|(live_in, live_out)| LVABlock { live_in, live_out })
             .collect();
 
-        fixed_point(&mut blocks, |blocks, _old| {
-            for i in 0..blocks.len() {
-                let mut live_out = blocks[i].phi_out.clone();
-                if let Some(Terminator::Return(p)) = &cfg.basic_blocks[i].terminator {
-                    live_out.insert(*p);
-                }
-                live_out.extend(cfg.successors(i).iter().flat_map(|&b| &blocks[b].live_in));
-
-                let block = &mut blocks[i];
-                block.live_out = live_out;
-                block.live_in = block
-                    .uevar
-                    .union(&block.live_out.difference(&block.def).cloned().collect())
-                    .cloned()
-                    .collect();
-            }
-        });
-
         Self { blocks }
     }
 
     pub fn point_lva(&self, cfg: &Cfg) -> HashMap<(usize, isize), HashSet<usize>> {
-        let mut plva = HashMap::new();
-
-        for (i, b) in cfg.basic_blocks.iter().enumerate() {
-            let blva = &self.blocks[i];
-            let mut living = blva.live_out.clone();
-
-            for (j, stmnt) in b.stmnts.iter().enumerate().rev() {
-                match stmnt {
-                    Statement::Assign(a) => {
-                        if living.remove(&a.place) {
-                            match &a.value {
-                                Value::Place(p) => living.extend([*p]),
-                                Value::Call { args, .. } => living.extend(args),
-                            };
-                        }
-                    }
-                    Statement::Nop
-                    | Statement::Deallocate(_)
-                    | Statement::Dup(_)
-                    | Statement::Drop(_) => {}
-                };
-
-                plva.insert((i, j as isize), living.clone());
-            }
-
-            for phi in &b.phi {
-                if living.remove(&phi.place) {
-                    living.extend(phi.opts.values());
-                }
-            }
-            plva.insert((i, -1), living);
-            plva.insert((i, b.stmnts.len() as isize), blva.live_out.clone());
-        }
-
-        plva
-    }
-}
+        let solution = Solution {
+            start: self.blocks.iter().map(|b| b.live_in.clone()).collect(),
+            end: self.blocks.iter().map(|b| b.live_out.clone()).collect(),
+        };
 
-pub fn fixed_point<T: Clone + PartialEq>(state: &mut T, mut f: impl FnMut(&mut T, &T)) {
-    loop {
-        let old = state.clone();
-
-        f(state, &old);
-
-        if old == *state {
-            break;
-        }
+        solution.points(cfg, &Liveness)
     }
 }
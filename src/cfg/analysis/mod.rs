@@ -1,17 +1,29 @@
+pub mod alias;
+pub mod cache;
+pub mod dataflow;
 pub mod deps;
+pub mod effects;
 pub mod lva;
+pub mod policy;
 pub mod recursion;
+pub mod threads;
+pub mod uniqueness;
 
-use std::collections::HashMap;
+use std::{collections::HashMap, path::PathBuf};
 
-use self::deps::DepGraph;
+use self::{deps::DepGraph, policy::AllocPolicy};
 
-use super::Cfg;
+use super::{mem_manage::Strategy, Cfg};
 use crate::{ast::Ident, types::Type};
 
 pub struct Context {
     pub fns: HashMap<Ident, Function>,
     pub type_map: HashMap<String, Type>,
+    pub cache_dir: Option<PathBuf>,
+    pub strategy: Strategy,
+    pub alloc_policy: AllocPolicy,
+    /// Copies of functions that return their value on the heap, and the function each copies
+    pub heap_copies: HashMap<Ident, Ident>,
 }
 
 pub struct Function {
@@ -24,6 +36,10 @@ impl Context {
         Self {
             fns: HashMap::new(),
             type_map: HashMap::new(),
+            cache_dir: None,
+            strategy: Strategy::RefCount,
+            alloc_policy: AllocPolicy::default(),
+            heap_copies: HashMap::new(),
         }
     }
 
@@ -58,6 +74,20 @@ impl Context {
             return Some(deps.clone());
         }
 
-        Some(self.compute_recursive_depgraph(&func.cfg.clone()))
+        let cfg = func.cfg.clone();
+        let deps = match self.load_summary(ident) {
+            Some(deps) => deps,
+            None => {
+                let deps = match self.heap_copies.contains_key(ident) {
+                    true => self.heap_summary(&cfg),
+                    false => self.compute_recursive_depgraph(&cfg),
+                };
+                self.store_summary(ident, &deps);
+                deps
+            }
+        };
+
+        self.set_depgraph(ident, deps.clone());
+        Some(deps)
     }
 }
@@ -0,0 +1,104 @@
+use std::{fmt, str::FromStr};
+
+use super::{deps::DepGraph, Context};
+use crate::{ast::Ident, cfg::Cfg};
+
+/// Decides which values the analysis puts on the heap.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum AllocPolicy {
+    /// Every function returns its value on the heap
+    AlwaysHeap,
+    /// Values that escape through a recursive call are put on the heap, without trying to
+    /// find a stack summary for the recursion
+    Escape,
+    /// Like `Escape`, but calls whose result has to be on the heap call a copy of the function
+    /// that builds it there, instead of copying its stack result over
+    EscapeMono,
+    /// Recursive summaries are solved to a fixed point, and only those that don't converge
+    /// put their result on the heap
+    #[default]
+    HeapUnconverged,
+}
+
+impl AllocPolicy {
+    pub const ALL: [Self; 4] = [
+        Self::AlwaysHeap,
+        Self::Escape,
+        Self::EscapeMono,
+        Self::HeapUnconverged,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::AlwaysHeap => "always-heap",
+            Self::Escape => "escape",
+            Self::EscapeMono => "escape-mono",
+            Self::HeapUnconverged => "heap-unconverged",
+        }
+    }
+
+    pub fn solves_recursion(self) -> bool {
+        self == Self::HeapUnconverged
+    }
+
+    pub fn monomorphizes(self) -> bool {
+        self == Self::EscapeMono
+    }
+}
+
+impl fmt::Display for AllocPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for AllocPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|p| p.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(|p| p.name()).collect();
+                format!(
+                    "unknown allocation policy {s}, expected one of {}",
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+impl Context {
+    /// Gets the copy of a function that returns its value on the heap, creating it if needed.
+    pub fn heap_specialization(&mut self, ident: &Ident) -> Ident {
+        if let Some((copy, _)) = self.heap_copies.iter().find(|(_, f)| *f == ident) {
+            return copy.clone();
+        }
+
+        // the module may have a function of its own by that name
+        let mut name = Ident(format!("{ident}__heap"));
+        let mut i = 1;
+        while self.fns.contains_key(&name) {
+            name = Ident(format!("{ident}__heap_{i}"));
+            i += 1;
+        }
+
+        let mut cfg = self.get_cfg(ident).unwrap().clone();
+        cfg.name = name.clone();
+        self.add_cfgs([cfg]);
+        self.heap_copies.insert(name.clone(), ident.clone());
+        self.compute_depgraph(&name);
+
+        name
+    }
+
+    // The summary of a heap copy, which is computed like any other except for where its
+    // result goes
+    pub(super) fn heap_summary(&mut self, cfg: &Cfg) -> DepGraph {
+        let args = (1..=cfg.arg_count).collect::<Vec<_>>();
+        let mut deps = DepGraph::from_cfg(self, cfg, true);
+        deps.simplify(&args);
+        deps
+    }
+}
@@ -1,22 +1,15 @@
 use crate::cfg::{Assign, BasicBlock, Cfg, Statement, Value};
 
-use super::{deps::DepGraph, Context};
-
-const ALLOC_ALWAYS: bool = false;
+use super::{
+    deps::{DepGraph, Escape},
+    policy::AllocPolicy,
+    Context,
+};
 
 impl Context {
     pub fn compute_recursive_depgraph(&mut self, cfg: &Cfg) -> DepGraph {
-        if ALLOC_ALWAYS {
-            eprintln!("Warning: ALLOC_ALWAYS is true.");
-
-            let mut opaque = DepGraph::opaque();
-            self.set_depgraph(&cfg.name, opaque.clone());
-
-            let internal_deps = DepGraph::from_cfg(self, &cfg, true);
-            opaque.alloced_args = internal_deps.alloced_args;
-
-            self.set_depgraph(&cfg.name, opaque.clone());
-            return opaque;
+        if self.alloc_policy == AllocPolicy::AlwaysHeap {
+            return self.opaque_summary(cfg, Escape::AllocAlways);
         }
 
         let mut no_recurse = cfg.clone();
@@ -41,6 +34,10 @@ impl Context {
             return deps;
         }
 
+        if !self.alloc_policy.solves_recursion() {
+            return self.opaque_summary(cfg, Escape::Recursive);
+        }
+
         for _ in 0..50 {
             // dot::render(
             //     &deps,
@@ -65,11 +62,18 @@ impl Context {
         }
 
         // We failed to converge, so just allocate the return value
+        self.opaque_summary(cfg, Escape::Unconverged)
+    }
+
+    // A summary that allocates the return value, keeping whichever arguments the body allocates
+    fn opaque_summary(&mut self, cfg: &Cfg, escape: Escape) -> DepGraph {
         let mut opaque = DepGraph::opaque();
+        opaque.escapes.insert(0, escape);
         self.set_depgraph(&cfg.name, opaque.clone());
 
-        let internal_deps = DepGraph::from_cfg(self, &cfg, true);
+        let internal_deps = DepGraph::from_cfg(self, cfg, true);
         opaque.alloced_args = internal_deps.alloced_args;
+        opaque.effect = opaque.effect.max(internal_deps.effect);
 
         self.set_depgraph(&cfg.name, opaque.clone());
         opaque
@@ -89,6 +93,7 @@ fn kill_linear_path(cfg: &mut Cfg, start: usize) {
                 phi: vec![],
                 stmnts: vec![],
                 terminator: None,
+                term_span: None,
             };
 
             for succ in succs {
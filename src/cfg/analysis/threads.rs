@@ -0,0 +1,152 @@
+use std::collections::{HashMap, HashSet};
+
+use super::deps::DepGraph;
+use crate::{
+    ast::Ident,
+    cfg::{Cfg, Statement, Value},
+};
+
+/// Finds the places of every function that may hold a value another thread can reach,
+/// which is anything passed to one of the `boundaries`, and follows them through calls
+/// and returns.
+///
+/// Everything a shared value holds is shared, and so is everything that holds it, since
+/// dropping a container drops what's inside of it too.
+pub fn thread_shared(
+    fns: &HashMap<Ident, (Cfg, DepGraph)>,
+    boundaries: &HashSet<Ident>,
+) -> HashMap<Ident, HashSet<usize>> {
+    let mut names: Vec<_> = fns.keys().collect();
+    names.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut shared: HashMap<Ident, HashSet<usize>> =
+        names.iter().map(|&n| (n.clone(), HashSet::new())).collect();
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+
+        for &name in &names {
+            let (cfg, deps) = &fns[name];
+
+            let mut seeds = shared[name].clone();
+            for (place, func, args) in calls(cfg) {
+                if is_boundary(func, boundaries) {
+                    seeds.extend(args);
+                }
+                if let Some(callee) = shared.get(func) {
+                    seeds.extend(
+                        args.iter()
+                            .zip(1..)
+                            .filter(|(_, i)| callee.contains(i))
+                            .map(|(a, _)| a),
+                    );
+                    if callee.contains(&0) {
+                        seeds.insert(place);
+                    }
+                }
+            }
+
+            let found = connected(deps, seeds, cfg.place_tys.len());
+
+            // Callees see the shared values they're passed. What they return is only shared
+            // once it gets here, so that doesn't flow back into them.
+            for (_, func, args) in calls(cfg) {
+                let Some(callee) = shared.get_mut(func) else {
+                    continue;
+                };
+
+                let before = callee.len();
+                callee.extend((1..=args.len()).filter(|i| found.contains(&args[i - 1])));
+                changed |= callee.len() != before;
+            }
+
+            let own = shared.get_mut(name).unwrap();
+            if *own != found {
+                *own = found;
+                changed = true;
+            }
+        }
+    }
+
+    shared
+}
+
+// Copies of a boundary that return on the heap hand their arguments over all the same
+fn is_boundary(func: &Ident, boundaries: &HashSet<Ident>) -> bool {
+    let base = func.0.strip_suffix("__heap").unwrap_or(&func.0);
+    boundaries.contains(&Ident(base.to_string()))
+}
+
+fn calls(cfg: &Cfg) -> impl Iterator<Item = (usize, &Ident, &Vec<usize>)> {
+    cfg.statements().filter_map(|s| match s {
+        Statement::Assign(a) => match &a.value {
+            Value::Call { func, args } => Some((a.place, func, args)),
+            Value::Place(_) => None,
+        },
+        _ => None,
+    })
+}
+
+// The seeds and everything they hold, followed by everything that holds any of those.
+// Whatever a container holds besides is only dropped through it, so it isn't shared.
+fn connected(deps: &DepGraph, seeds: HashSet<usize>, places: usize) -> HashSet<usize> {
+    let mut holders: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (node, n) in deps.nodes.iter().enumerate() {
+        for &dep in n.deps.get() {
+            holders.entry(dep).or_default().push(node);
+        }
+    }
+
+    let held = closure(seeds, |n| deps.nodes[n].deps.get().clone());
+    let mut found = closure(held, |n| holders.get(&n).cloned().unwrap_or_default());
+
+    found.retain(|&p| p < places);
+    found
+}
+
+fn closure(start: HashSet<usize>, next: impl Fn(usize) -> Vec<usize>) -> HashSet<usize> {
+    let mut found = start.clone();
+    let mut stack: Vec<_> = start.into_iter().collect();
+    while let Some(node) = stack.pop() {
+        for n in next(node) {
+            if found.insert(n) {
+                stack.push(n);
+            }
+        }
+    }
+
+    found
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cfg::test_util::managed_module;
+
+    #[test]
+    fn test_shared_through_calls() {
+        let (fns, _) = managed_module(include_str!("../../../inputs/threads.rs"));
+
+        let shared = thread_shared(&fns, &HashSet::from(["spawn".into()]));
+
+        assert!(shared[&"spawn".into()].contains(&1));
+        assert!(shared[&"send".into()].contains(&1));
+        assert!(shared[&"generate".into()].is_empty());
+
+        let main = &fns[&"main".into()].0;
+        let sent = calls(main)
+            .find(|(_, func, _)| func.0 == "send")
+            .map(|(_, _, args)| args[0])
+            .unwrap();
+        let kept = calls(main)
+            .find(|(place, func, _)| func.0.starts_with("generate") && *place != sent)
+            .map(|(place, _, _)| place)
+            .unwrap();
+
+        // `kept` is returned alongside what `send` gives back, which has nothing of `sent`
+        let main = &shared[&"main".into()];
+        assert!(main.contains(&sent));
+        assert!(!main.contains(&kept) && !main.contains(&0));
+    }
+}
@@ -0,0 +1,47 @@
+use std::collections::HashMap;
+
+use crate::cfg::{Cfg, RefCount, Statement};
+
+use super::{
+    deps::{DepGraph, Share},
+    lva::LVA,
+};
+
+/// The share of every referenced place at each program point, meaning whether the
+/// reference it holds is statically known to be the only one to its value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Uniqueness {
+    pub points: HashMap<(usize, isize), Vec<Option<Share>>>,
+}
+
+impl Uniqueness {
+    // Should be run on a cfg that already has its dup/drop operations inserted
+    pub fn analyze(cfg: &Cfg, deps: &DepGraph) -> Self {
+        let aliases = deps.aliases();
+        let points = LVA::analyze(cfg)
+            .point_lva(cfg)
+            .into_iter()
+            .map(|((b, j), mut live)| {
+                // a dropped place still holds its reference until the drop itself
+                let stmnt = usize::try_from(j)
+                    .ok()
+                    .and_then(|j| cfg.basic_blocks[b].stmnts.get(j));
+                if let Some(Statement::Drop(RefCount { place, .. })) = stmnt {
+                    live.insert(*place);
+                }
+
+                ((b, j), deps.shares(&aliases, &live, cfg.arg_count))
+            })
+            .collect();
+
+        Self { points }
+    }
+
+    /// Whether the reference held by `place` is the only one at the given program point.
+    pub fn exclusive_at(&self, point: (usize, isize), place: usize) -> bool {
+        self.points
+            .get(&point)
+            .and_then(|shares| shares.get(place).copied().flatten())
+            == Some(Share::Exclusive)
+    }
+}
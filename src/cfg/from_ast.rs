@@ -12,6 +12,8 @@ pub struct ConversionState {
     pub type_map: HashMap<String, Type>,
     pub scopes: Vec<HashMap<ast::Ident, usize>>,
     pub last_block: usize,
+    /// The source being converted, which everything added to the cfg is attributed to
+    pub span: Option<ast::Span>,
 }
 
 impl ConversionState {
@@ -25,6 +27,7 @@ impl ConversionState {
             type_map,
             scopes: Vec::new(),
             last_block: 0,
+            span: None,
         };
 
         this.push_scope();
@@ -32,7 +35,9 @@ impl ConversionState {
             this.set_place_scoped(arg.name, i + 1);
         }
 
+        let ret_span = func.body.ret_span;
         let ret = this.add_block(func.body);
+        this.span = Some(ret_span);
         this.set_terminator(Terminator::Return(ret));
 
         this.cfg
@@ -56,12 +61,15 @@ impl ConversionState {
                 place,
                 value,
                 allocate: false,
+                span: self.span,
             }));
         place
     }
 
     pub fn set_terminator(&mut self, terminator: Terminator) {
-        self.cfg.basic_blocks[self.last_block].terminator = Some(terminator);
+        let block = &mut self.cfg.basic_blocks[self.last_block];
+        block.terminator = Some(terminator);
+        block.term_span = self.span;
     }
 
     pub fn add_call(&mut self, call: ast::Call) -> usize {
@@ -112,15 +120,19 @@ impl ConversionState {
     }
 
     pub fn add_block(&mut self, block: ast::Block) -> usize {
+        let outer_span = self.span;
         self.push_scope();
 
         for stmnt in block.stmnts {
+            self.span = Some(stmnt.span);
             let value = self.add_expr(stmnt.value);
             self.set_place_scoped(stmnt.ident, value);
         }
 
+        self.span = Some(block.ret_span);
         let ret = self.add_expr(block.ret);
         self.pop_scope();
+        self.span = outer_span;
         ret
     }
 
@@ -157,6 +169,7 @@ impl ConversionState {
     }
 
     pub fn set_place_scoped(&mut self, ident: ast::Ident, place: usize) {
+        self.cfg.debug_names.insert(place, ident.clone());
         self.scopes.last_mut().unwrap().insert(ident, place);
     }
 
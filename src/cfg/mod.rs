@@ -1,29 +1,44 @@
 pub mod analysis;
 pub mod from_ast;
 pub mod mem_manage;
+pub mod optimize;
 pub mod render;
+#[cfg(test)]
+pub mod test_util;
 
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+};
 
 use crate::{ast, types::Type};
 use from_ast::ConversionState;
 
-#[derive(Clone)]
+#[derive(Clone, Hash)]
 pub struct Cfg {
     pub name: ast::Ident,
     pub arg_count: usize,
     pub place_tys: Vec<Type>,
     pub basic_blocks: Vec<BasicBlock>,
+    pub debug_names: DebugNames,
 }
 
-#[derive(Clone)]
+/// The names places had in the source, for anything that shows places to people.
+/// Arguments and `let` bindings have one, while temporaries and places the compiler adds
+/// don't.
+#[derive(Clone, Default)]
+pub struct DebugNames(HashMap<usize, ast::Ident>);
+
+#[derive(Clone, Hash)]
 pub struct BasicBlock {
     pub phi: Vec<Phi>,
     pub stmnts: Vec<Statement>,
     pub terminator: Option<Terminator>,
+    /// The source the terminator came from
+    pub term_span: Option<ast::Span>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Hash)]
 pub enum Statement {
     Assign(Assign),
     Deallocate(usize),
@@ -32,7 +47,7 @@ pub enum Statement {
     Nop,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Hash)]
 pub struct RefCount {
     pub place: usize,
     pub count: usize,
@@ -44,20 +59,22 @@ pub struct Phi {
     pub opts: HashMap<usize, usize>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Hash)]
 pub struct Assign {
     pub place: usize,
     pub value: Value,
     pub allocate: bool,
+    /// The source the assignment came from, which is missing for ones the compiler adds
+    pub span: Option<ast::Span>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Hash)]
 pub enum Value {
     Place(usize),
     Call { func: ast::Ident, args: Vec<usize> },
 }
 
-#[derive(Clone)]
+#[derive(Clone, Hash)]
 pub enum Terminator {
     Goto(usize),
     Return(usize),
@@ -86,7 +103,9 @@ impl Cfg {
                 phi: vec![],
                 stmnts: vec![],
                 terminator: None,
+                term_span: None,
             }],
+            debug_names: DebugNames::default(),
         }
     }
 
@@ -103,10 +122,37 @@ impl Cfg {
             phi: vec![],
             stmnts: vec![],
             terminator: None,
+            term_span: None,
         });
         out
     }
 
+    /// Puts a new block on the edge between two blocks, returning the new block.
+    pub fn split_edge(&mut self, from: usize, to: usize) -> usize {
+        let mid = self.add_bb();
+        self.basic_blocks[mid].terminator = Some(Terminator::Goto(to));
+
+        match &mut self.basic_blocks[from].terminator {
+            Some(Terminator::Goto(next)) => *next = mid,
+            Some(Terminator::IfElse { iff, elsee, .. }) => {
+                for target in [iff, elsee] {
+                    if *target == to {
+                        *target = mid;
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        for phi in &mut self.basic_blocks[to].phi {
+            if let Some(opt) = phi.opts.remove(&from) {
+                phi.opts.insert(mid, opt);
+            }
+        }
+
+        mid
+    }
+
     /// This could not cover every block if unreachable blocks exist.
     pub fn bb_order(&self) -> Vec<usize> {
         let mut order = vec![0];
@@ -170,6 +216,43 @@ impl RefCount {
     }
 }
 
+// Phi options are hashed in block order so that equal phis always hash the same
+impl Hash for Phi {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.place.hash(state);
+
+        let mut opts: Vec<_> = self.opts.iter().collect();
+        opts.sort();
+        opts.hash(state);
+    }
+}
+
+impl DebugNames {
+    pub fn get(&self, place: usize) -> Option<&ast::Ident> {
+        self.0.get(&place)
+    }
+
+    /// Names a place, unless it already has a name. A place bound again by a later `let`
+    /// keeps the name it was introduced with.
+    pub fn insert(&mut self, place: usize, name: ast::Ident) {
+        self.0.entry(place).or_insert(name);
+    }
+
+    /// How a place is shown, like `list_3`, or `_3` if it has no name.
+    pub fn show(&self, place: usize) -> String {
+        match self.get(place) {
+            Some(name) => format!("{name}_{place}"),
+            None => format!("_{place}"),
+        }
+    }
+}
+
+// Renaming a variable doesn't change what a function does, so names are left out of the
+// hashes that analysis summaries are cached by
+impl Hash for DebugNames {
+    fn hash<H: Hasher>(&self, _state: &mut H) {}
+}
+
 impl BasicBlock {
     // this iterator can produce the same place multiple times
     pub fn phi_used_vars(&self) -> impl Iterator<Item = &usize> {
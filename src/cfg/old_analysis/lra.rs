@@ -4,6 +4,7 @@ use std::{
 };
 
 use crate::cfg::{
+    analysis::lva::LVA,
     old_analysis::{
         deps::{add_ctrs, DepGraph},
         signature::ArgLives,
@@ -11,7 +12,7 @@ use crate::cfg::{
     Cfg, Terminator,
 };
 
-use super::{context::Context, deps::Deps, lva::LVA, signature::ReturnLives};
+use super::{context::Context, deps::Deps, signature::ReturnLives};
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Perm {
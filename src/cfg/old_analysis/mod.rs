@@ -1,7 +1,5 @@
 pub mod context;
 pub mod deps;
-pub mod fixed_point;
 pub mod lra;
-pub mod lva;
 pub mod recursion;
 pub mod signature;
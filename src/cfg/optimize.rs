@@ -0,0 +1,97 @@
+use super::{
+    analysis::{lva::LVA, Context},
+    Cfg, Statement, Value,
+};
+
+/// Turns assignments whose place is never read into `Nop`s, as long as computing them has
+/// no effect anyone could observe. The function's summary is recomputed from what's left.
+/// Returns how many were removed.
+pub fn eliminate_dead_calls(ctx: &mut Context, cfg: &mut Cfg) -> usize {
+    let mut removed = 0;
+
+    // removing a call can make its arguments dead too
+    loop {
+        let plva = LVA::analyze(cfg).point_lva(cfg);
+        let mut changed = false;
+
+        for (b, block) in cfg.basic_blocks.iter_mut().enumerate() {
+            for (j, stmnt) in block.stmnts.iter_mut().enumerate() {
+                let Statement::Assign(a) = stmnt else {
+                    continue;
+                };
+
+                if plva[&(b, j as isize + 1)].contains(&a.place) {
+                    continue;
+                }
+
+                let removable = match &a.value {
+                    Value::Place(_) => true,
+                    Value::Call { func, .. } => ctx.call_effect(func).removable(),
+                };
+
+                if removable {
+                    *stmnt = Statement::Nop;
+                    removed += 1;
+                    changed = true;
+                }
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+
+    if removed > 0 {
+        ctx.update_cfg(cfg.clone());
+    }
+
+    removed
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cfg::{analysis::effects::Effect, test_util::context};
+
+    #[test]
+    fn test_effects_and_dead_calls() {
+        let src = format!(
+            "{}\n\
+             fn unit(c: ()) -> () {{ tuple() }}\n\
+             fn say(c: ()) -> () {{ print(c) }}\n\
+             fn relay(c: ()) -> () {{ say(c) }}\n\
+             fn unused(c: ()) -> () {{ let l = generate(c); let u = unit(c); let s = relay(c); c }}\n\
+             fn waste(c: ()) -> () {{ let l = generate(c); c }}",
+            include_str!("../../inputs/gen_list.rs")
+        );
+        let mut ctx = context(&src);
+
+        assert_eq!(ctx.call_effect(&"unit".into()), Effect::Pure);
+        assert_eq!(ctx.call_effect(&"generate".into()), Effect::Allocates);
+        assert_eq!(ctx.call_effect(&"relay".into()), Effect::Effectful);
+        assert_eq!(ctx.call_effect(&"unused".into()), Effect::Effectful);
+
+        // only the call that prints survives
+        let mut cfg = ctx.get_cfg(&"unused".into()).unwrap().clone();
+        assert_eq!(eliminate_dead_calls(&mut ctx, &mut cfg), 2);
+        let calls: Vec<_> = cfg
+            .statements()
+            .filter_map(|s| match s {
+                Statement::Assign(a) => match &a.value {
+                    Value::Call { func, .. } => Some(func.0.as_str()),
+                    Value::Place(_) => None,
+                },
+                _ => None,
+            })
+            .collect();
+        assert_eq!(calls, ["relay"]);
+
+        // the summary is recomputed once the only allocation is gone
+        let waste = "waste".into();
+        assert_eq!(ctx.call_effect(&waste), Effect::Allocates);
+        let mut cfg = ctx.get_cfg(&waste).unwrap().clone();
+        assert_eq!(eliminate_dead_calls(&mut ctx, &mut cfg), 1);
+        assert_eq!(ctx.call_effect(&waste), Effect::Pure);
+    }
+}
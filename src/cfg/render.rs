@@ -1,20 +1,23 @@
 use std::fmt;
 
-use super::{BasicBlock, Cfg, Statement, Terminator, Value};
+use super::{BasicBlock, Cfg, DebugNames, Statement, Terminator, Value};
 
 impl fmt::Debug for Cfg {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = &self.debug_names;
+
         write!(f, "Cfg")?;
-        fmt_arglist(f, 1..=self.arg_count)?;
+        fmt_arglist(f, 1..=self.arg_count, names)?;
         writeln!(f, ":")?;
 
         for (place, ty) in self.place_tys.iter().enumerate() {
-            write!(f, "_{place}: {ty:?}, ")?;
+            write!(f, "{}: {ty:?}, ", names.show(place))?;
         }
         writeln!(f, "")?;
 
         for (i, bb) in self.basic_blocks.iter().enumerate() {
-            write!(f, "{i}: {bb:#?}")?;
+            write!(f, "{i}: ")?;
+            fmt_block(f, bb, names)?;
 
             if i + 1 != self.basic_blocks.len() {
                 writeln!(f)?;
@@ -25,77 +28,106 @@ impl fmt::Debug for Cfg {
     }
 }
 
+// Blocks, values and terminators don't know the names of the places they use, so on their
+// own they're shown with bare place numbers
 impl fmt::Debug for BasicBlock {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "{{")?;
+        fmt_block(f, self, &DebugNames::default())
+    }
+}
 
-        for phi in &self.phi {
-            write!(f, "\tlet _{} = ϕ", phi.place)?;
-            fmt_named_arglist(f, phi.opts.clone())?;
-            writeln!(f, ";")?;
-        }
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_value(f, self, &DebugNames::default())
+    }
+}
 
-        for stmnt in &self.stmnts {
-            write!(f, "\t")?;
-            match stmnt {
-                Statement::Assign(a) => {
-                    let prefix = match a.allocate {
-                        true => "allocate ",
-                        false => "",
-                    };
-
-                    write!(f, "let _{} = {prefix}{:?}", a.place, a.value)?
-                }
-                Statement::Nop => write!(f, "nop")?,
-                Statement::Deallocate(p) => write!(f, "deallocate _{p:?}")?,
-                Statement::Dup(r) => write!(f, "dup+{} _{}", r.count, r.place)?,
-                Statement::Drop(r) => write!(f, "drop-{} _{}", r.count, r.place)?,
+impl fmt::Debug for Terminator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_terminator(f, self, &DebugNames::default())
+    }
+}
+
+fn fmt_block(f: &mut fmt::Formatter<'_>, bb: &BasicBlock, names: &DebugNames) -> fmt::Result {
+    writeln!(f, "{{")?;
+
+    for phi in &bb.phi {
+        write!(f, "\tlet {} = ϕ", names.show(phi.place))?;
+        fmt_named_arglist(f, phi.opts.clone(), names)?;
+        writeln!(f, ";")?;
+    }
+
+    for stmnt in &bb.stmnts {
+        write!(f, "\t")?;
+        match stmnt {
+            Statement::Assign(a) => {
+                let prefix = match a.allocate {
+                    true => "allocate ",
+                    false => "",
+                };
+
+                write!(f, "let {} = {prefix}", names.show(a.place))?;
+                fmt_value(f, &a.value, names)?;
             }
-            writeln!(f, ";")?;
+            Statement::Nop => write!(f, "nop")?,
+            Statement::Deallocate(p) => write!(f, "deallocate {}", names.show(*p))?,
+            Statement::Dup(r) => write!(f, "dup+{} {}", r.count, names.show(r.place))?,
+            Statement::Drop(r) => write!(f, "drop-{} {}", r.count, names.show(r.place))?,
         }
+        writeln!(f, ";")?;
+    }
 
-        if let Some(terminator) = &self.terminator {
-            writeln!(f, "\t{:?}", terminator)?;
-        } else {
-            writeln!(f, "\tdeadend")?;
-        }
+    if let Some(terminator) = &bb.terminator {
+        write!(f, "\t")?;
+        fmt_terminator(f, terminator, names)?;
+        writeln!(f)?;
+    } else {
+        writeln!(f, "\tdeadend")?;
+    }
 
-        writeln!(f, "}}")?;
+    writeln!(f, "}}")?;
 
-        Ok(())
-    }
+    Ok(())
 }
 
-impl fmt::Debug for Value {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Value::Place(p) => write!(f, "_{p}"),
-            Value::Call { func, args } => {
-                write!(f, "{}", func.0)?;
-                fmt_arglist(f, args.clone())
-            }
+fn fmt_value(f: &mut fmt::Formatter<'_>, value: &Value, names: &DebugNames) -> fmt::Result {
+    match value {
+        Value::Place(p) => write!(f, "{}", names.show(*p)),
+        Value::Call { func, args } => {
+            write!(f, "{}", func.0)?;
+            fmt_arglist(f, args.clone(), names)
         }
     }
 }
 
-impl fmt::Debug for Terminator {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::Goto(bb) => write!(f, "goto -> {bb}"),
-            Self::Return(p) => write!(f, "return _{p}"),
-            Self::IfElse { cond, iff, elsee } => {
-                write!(f, "goto -> if _{cond} {{ {iff} }} else {{ {elsee} }}")
-            }
+fn fmt_terminator(
+    f: &mut fmt::Formatter<'_>,
+    terminator: &Terminator,
+    names: &DebugNames,
+) -> fmt::Result {
+    match terminator {
+        Terminator::Goto(bb) => write!(f, "goto -> {bb}"),
+        Terminator::Return(p) => write!(f, "return {}", names.show(*p)),
+        Terminator::IfElse { cond, iff, elsee } => {
+            write!(
+                f,
+                "goto -> if {} {{ {iff} }} else {{ {elsee} }}",
+                names.show(*cond)
+            )
         }
     }
 }
 
-fn fmt_arglist(f: &mut fmt::Formatter<'_>, args: impl IntoIterator<Item = usize>) -> fmt::Result {
+fn fmt_arglist(
+    f: &mut fmt::Formatter<'_>,
+    args: impl IntoIterator<Item = usize>,
+    names: &DebugNames,
+) -> fmt::Result {
     write!(f, "(")?;
 
     let mut args = args.into_iter().peekable();
     while let Some(arg) = args.next() {
-        write!(f, "_{arg}")?;
+        write!(f, "{}", names.show(arg))?;
         if args.peek().is_some() {
             write!(f, ", ")?;
         }
@@ -107,12 +139,13 @@ fn fmt_arglist(f: &mut fmt::Formatter<'_>, args: impl IntoIterator<Item = usize>
 fn fmt_named_arglist<N: fmt::Debug>(
     f: &mut fmt::Formatter<'_>,
     args: impl IntoIterator<Item = (N, usize)>,
+    names: &DebugNames,
 ) -> fmt::Result {
     write!(f, "(")?;
 
     let mut args = args.into_iter().peekable();
     while let Some((name, arg)) = args.next() {
-        write!(f, "{name:?}: _{arg}")?;
+        write!(f, "{name:?}: {}", names.show(arg))?;
         if args.peek().is_some() {
             write!(f, ", ")?;
         }
@@ -120,3 +153,29 @@ fn fmt_named_arglist<N: fmt::Debug>(
 
     write!(f, ")")
 }
+
+#[cfg(test)]
+mod test {
+    use crate::{cfg::Cfg, parser};
+
+    #[test]
+    fn test_debug_names() {
+        let module = parser::ModuleParser::new()
+            .parse(
+                "fn f(a: ()) -> ((),) {
+                    let b = tuple(a);
+                    let c = b;
+                    tuple(invent())
+                }",
+            )
+            .unwrap();
+        let cfg = Cfg::from_ast(module.fns[0].clone(), Default::default());
+
+        let rendered = format!("{cfg:?}");
+        assert!(rendered.starts_with("Cfg(a_1):"));
+        assert!(rendered.contains("let b_2 = tuple(a_1);"));
+        // rebinding keeps the first name, and temporaries don't have one
+        assert!(!rendered.contains("c_2"));
+        assert!(rendered.contains("let _3 = invent();"));
+    }
+}
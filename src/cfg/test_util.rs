@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+
+use super::{
+    analysis::{deps::DepGraph, Context},
+    mem_manage, Cfg,
+};
+use crate::{ast::Ident, parser, types::Type};
+
+/// A context holding every function of `src`, with the types of its definitions and
+/// function results.
+pub fn context(src: &str) -> Context {
+    let module = parser::ModuleParser::new().parse(src).unwrap();
+
+    let mut type_map = module.ty_defs;
+    type_map.extend(
+        module
+            .fns
+            .iter()
+            .map(|f| (f.name.0.clone(), f.ret_ty.clone())),
+    );
+
+    let mut ctx = Context::new();
+    ctx.add_cfgs(
+        module
+            .fns
+            .into_iter()
+            .map(|f| Cfg::from_ast(f, type_map.clone())),
+    );
+    ctx.type_map = type_map;
+    ctx
+}
+
+/// Every function of `src` (and any heap copies of them) with memory management inserted,
+/// the way the driver hands them to a backend.
+pub fn managed_module(src: &str) -> (HashMap<Ident, (Cfg, DepGraph)>, HashMap<String, Type>) {
+    manage(context(src))
+}
+
+/// Inserts memory management into every function of the context, for contexts that need
+/// setting up first.
+pub fn manage(mut ctx: Context) -> (HashMap<Ident, (Cfg, DepGraph)>, HashMap<String, Type>) {
+    let mut fns = HashMap::new();
+    while let Some(name) = ctx.fns.keys().find(|n| !fns.contains_key(*n)).cloned() {
+        let mut cfg = ctx.get_cfg(&name).unwrap().clone();
+        let deps = mem_manage::insert_management(&mut ctx, &mut cfg);
+        fns.insert(name, (cfg, deps));
+    }
+
+    (fns, ctx.type_map)
+}
@@ -1,16 +1,22 @@
-use std::{collections::HashMap, env, fs, path::PathBuf};
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
 
 use perm_mem::{
-    backend::compile_module_to_dir,
+    backend::{compile_module, create_backend, Options, Source},
     cfg::{
-        analysis::{deps::DepGraph, Context},
-        mem_manage, Cfg,
+        analysis::{cache::module_cache_dir, lva::LVA, Context},
+        mem_manage::{self, Strategy},
+        optimize, Cfg,
     },
     parser,
 };
 
 fn main() {
-    let path = PathBuf::from(env::args().nth(1).unwrap());
+    let args: Vec<_> = env::args().skip(1).collect();
+    let path = PathBuf::from(args.iter().find(|a| !a.starts_with("--")).unwrap());
     let input = fs::read_to_string(&path).unwrap();
 
     let parser = parser::ModuleParser::new();
@@ -28,6 +34,13 @@ fn main() {
     );
 
     let mut ctx = Context::new();
+    ctx.cache_dir = Some(module_cache_dir(Path::new("cache/"), &path));
+    if args.iter().any(|a| a == "--regions") {
+        ctx.strategy = Strategy::Region;
+    }
+    if let Some(policy) = args.iter().find_map(|a| a.strip_prefix("--alloc=")) {
+        ctx.alloc_policy = policy.parse().unwrap_or_else(|e| panic!("{e}"));
+    }
     ctx.add_cfgs(
         module
             .fns
@@ -36,15 +49,28 @@ fn main() {
     );
     ctx.type_map = type_map;
 
-    let names: Vec<_> = ctx.fns.iter().map(|(n, _)| n.clone()).collect();
-    let mut managed_cfgs = HashMap::new();
+    // every function is optimized before any is managed, so callers are managed against the
+    // summaries of what their callees still do
+    let names: Vec<_> = ctx.fns.keys().cloned().collect();
     for name in names {
+        let mut cfg = ctx.get_cfg(&name).unwrap().clone();
+        optimize::eliminate_dead_calls(&mut ctx, &mut cfg);
+    }
+
+    // managing a function can add specializations of the ones it calls
+    let mut managed_cfgs = HashMap::new();
+    while let Some(name) = ctx
+        .fns
+        .keys()
+        .find(|n| !managed_cfgs.contains_key(*n))
+        .cloned()
+    {
         let mut cfg = ctx.get_cfg(&name).unwrap().clone();
         // println!("{name}: {:?}\n", cfg);
 
         // println!("{:?}", DepGraph::from_cfg(&mut ctx, &cfg, true));
-        let ret_alloced = ctx.compute_depgraph(&name).unwrap().nodes[0].allocated();
-        let deps = DepGraph::from_cfg(&mut ctx, &cfg, ret_alloced);
+        let mut deps = mem_manage::insert_management(&mut ctx, &mut cfg);
+        deps.assign_shares(&cfg, &LVA::analyze(&cfg).point_lva(&cfg));
 
         {
             let deps = deps.clone();
@@ -52,7 +78,7 @@ fn main() {
 
             fs::create_dir_all("renders/").unwrap();
             dot::render(
-                &deps,
+                &deps.named(&cfg.debug_names),
                 &mut std::fs::File::create(&format!(
                     "renders/{}.{name}.dot",
                     path.file_name().unwrap().to_str().unwrap()
@@ -62,10 +88,39 @@ fn main() {
             .unwrap();
         }
 
-        mem_manage::insert_management(&mut ctx, &mut cfg);
         println!("{name}: {:?}\n", cfg);
+        for place in (0..cfg.place_tys.len()).filter(|p| deps.nodes[*p].allocated()) {
+            println!("{}", deps.explain(place));
+        }
+        println!();
+
         managed_cfgs.insert(cfg.name.clone(), (cfg, deps));
     }
 
-    compile_module_to_dir("build", managed_cfgs, &ctx.type_map).unwrap();
+    // Programs with a `main` run it unless told to start somewhere else
+    let entry = match args.iter().find_map(|a| a.strip_prefix("--entry=")) {
+        Some(name) => Some(name.into()),
+        None => Some("main".into()).filter(|m| managed_cfgs.contains_key(m)),
+    };
+    let opts = Options {
+        strategy: ctx.strategy,
+        debug_runtime: args.iter().any(|a| a == "--debug-runtime"),
+        alloc_hooks: args.iter().any(|a| a == "--alloc-hooks"),
+        pools: args.iter().any(|a| a == "--pools"),
+        atomic_rc: args.iter().any(|a| a == "--atomic-rc"),
+        thread_boundaries: args
+            .iter()
+            .filter_map(|a| a.strip_prefix("--thread-boundary="))
+            .flat_map(|names| names.split(','))
+            .map(|name| name.into())
+            .collect(),
+        entry,
+        source: Some(Source::new(&path, input)),
+    };
+    let backend_name = args
+        .iter()
+        .find_map(|a| a.strip_prefix("--backend="))
+        .unwrap_or("c");
+    let mut backend = create_backend(backend_name, "build").unwrap_or_else(|e| panic!("{e}"));
+    compile_module(&mut *backend, &managed_cfgs, &ctx.type_map, opts).unwrap();
 }